@@ -0,0 +1,122 @@
+//! Static analyses of loaded rules, for `mlatu check`.
+
+pub mod completion;
+pub mod confluence;
+pub mod termination;
+
+use std::collections::HashSet;
+
+use im::Vector;
+use mlatu_lib::{Rule, Term};
+
+use crate::eval::Limits;
+
+/// The limits on rewriting the small programs made from the redexes of
+/// rules.
+const LIMITS:Limits = Limits { steps:Some(1000), size:Some(10_000), time:None };
+
+/// Adds the atoms in some terms to `found`, including those inside quotes.
+fn atoms(terms:&Vector<Term>, found:&mut HashSet<Term>) {
+  for term in terms {
+    match term {
+      | Term::Quote(terms) => atoms(terms, found),
+      | _ => {
+        let _new = found.insert(term.clone());
+      },
+    }
+  }
+}
+
+/// The number of occurrences of some atoms in some terms, including those
+/// inside quotes.
+fn occurrences(terms:&Vector<Term>, of:&HashSet<Term>) -> usize {
+  terms.iter()
+       .map(|term| match term {
+         | Term::Quote(terms) => occurrences(terms, of),
+         | _ => usize::from(of.contains(term)),
+       })
+       .sum()
+}
+
+/// Which rules can use the terms another rule produces: rule `i` leads to
+/// rule `j` if the reduction of `i` contains an atom of the redex of `j`.
+fn dependencies(rules:&Vector<Rule>) -> Vec<Vec<usize>> {
+  let redexes = rules.iter()
+                     .map(|rule| {
+                       let mut found = HashSet::new();
+                       atoms(&rule.redex, &mut found);
+                       found
+                     })
+                     .collect::<Vec<_>>();
+  rules.iter()
+       .map(|rule| {
+         let mut produced = HashSet::new();
+         atoms(&rule.reduction, &mut produced);
+         redexes.iter()
+                .enumerate()
+                .filter(|(_, redex)| !redex.is_disjoint(&produced))
+                .map(|(j, _)| j)
+                .collect()
+       })
+       .collect()
+}
+
+/// The strongly connected components of a graph, given as the successors of
+/// each node, using Tarjan's algorithm. Each component is sorted.
+fn components(edges:&[Vec<usize>]) -> Vec<Vec<usize>> {
+  struct Search<'a> {
+    edges:&'a [Vec<usize>],
+    index:Vec<Option<usize>>,
+    low:Vec<usize>,
+    stack:Vec<usize>,
+    on_stack:Vec<bool>,
+    next:usize,
+    components:Vec<Vec<usize>>,
+  }
+
+  impl Search<'_> {
+    fn visit(&mut self, node:usize) {
+      self.index[node] = Some(self.next);
+      self.low[node] = self.next;
+      self.next += 1;
+      self.stack.push(node);
+      self.on_stack[node] = true;
+      for &next in &self.edges[node] {
+        match self.index[next] {
+          | None => {
+            self.visit(next);
+            self.low[node] = self.low[node].min(self.low[next]);
+          },
+          | Some(index) if self.on_stack[next] => self.low[node] = self.low[node].min(index),
+          | Some(_) => {},
+        }
+      }
+      if Some(self.low[node]) == self.index[node] {
+        let mut component = Vec::new();
+        while let Some(member) = self.stack.pop() {
+          self.on_stack[member] = false;
+          component.push(member);
+          if member == node {
+            break
+          }
+        }
+        component.sort_unstable();
+        self.components.push(component);
+      }
+    }
+  }
+
+  let mut search = Search { edges,
+                            index:vec![None; edges.len()],
+                            low:vec![0; edges.len()],
+                            stack:Vec::new(),
+                            on_stack:vec![false; edges.len()],
+                            next:0,
+                            components:Vec::new() };
+  for node in 0..edges.len() {
+    if search.index[node].is_none() {
+      search.visit(node);
+    }
+  }
+  search.components
+}
@@ -0,0 +1,141 @@
+//! Proposing rules that make the critical pairs of some rules join, in the
+//! style of Knuth-Bendix completion.
+//!
+//! The two normal forms of each critical pair that does not join are
+//! oriented by a term order into a new rule from the larger to the smaller,
+//! and the critical pairs are computed again with the new rules added, until
+//! every pair joins or a limit is reached.
+
+use std::cmp::Ordering;
+
+use im::Vector;
+use mlatu_lib::{Engine, Rule, Term};
+
+use super::confluence::{self, Divergence};
+use crate::eval::{size, Evaluator};
+use crate::{pretty_terms, Library};
+
+/// How the two sides of a critical pair are compared, to decide which one
+/// becomes the redex of a new rule. Sides that are equal in the order are
+/// compared as text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+  /// The side with more terms, counting those inside quotes, is larger
+  Size,
+  /// The side with more top-level terms is larger, then the one with more
+  /// terms counting those inside quotes
+  Length,
+}
+
+impl Order {
+  /// Looks up an order by name.
+  #[must_use]
+  pub fn from_name(name:&str) -> Option<Self> {
+    match name {
+      | "size" => Some(Self::Size),
+      | "length" => Some(Self::Length),
+      | _ => None,
+    }
+  }
+
+  /// Compares two programs.
+  #[must_use]
+  pub fn compare(self, engine:&Engine, a:&Vector<Term>, b:&Vector<Term>) -> Ordering {
+    let by_order = match self {
+      | Self::Size => size(a).cmp(&size(b)),
+      | Self::Length => a.len().cmp(&b.len()).then_with(|| size(a).cmp(&size(b))),
+    };
+    by_order.then_with(|| pretty_terms(engine, a.clone()).cmp(&pretty_terms(engine, b.clone())))
+  }
+}
+
+/// The limits on completion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+  /// The most rounds of proposing rules for the critical pairs that do not
+  /// join, each followed by computing the critical pairs again
+  pub rounds:usize,
+  /// The most rules proposed
+  pub rules:usize,
+}
+
+impl Default for Limits {
+  fn default() -> Self { Self { rounds:10, rules:100 } }
+}
+
+/// The result of completion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+  /// The proposed rules, in the order they were found
+  pub rules:Vec<Rule>,
+  /// The number of rounds in which rules were proposed
+  pub rounds:usize,
+  /// The critical pairs that still do not join with the proposed rules
+  /// added, which is empty if the rules are now locally confluent
+  pub remaining:Vec<Divergence>,
+}
+
+/// Proposes rules to add to a library so that its critical pairs join.
+#[must_use]
+pub fn complete(engine:&Engine, library:&Library, order:Order, limits:&Limits) -> Completion {
+  let mut completed = library.clone();
+  let mut proposed = Vec::new();
+  let mut rounds = 0;
+  let mut remaining = confluence::analyse(&Evaluator::new(engine, &completed));
+  while !remaining.is_empty() && rounds < limits.rounds && proposed.len() < limits.rules {
+    rounds += 1;
+    let before = proposed.len();
+    for divergence in &remaining {
+      if proposed.len() >= limits.rules {
+        break
+      }
+      let (redex, reduction) = match order.compare(engine, &divergence.left, &divergence.right) {
+        | Ordering::Greater => (&divergence.left, &divergence.right),
+        | _ => (&divergence.right, &divergence.left),
+      };
+      // Another pair of this round may already have given the same redex
+      if completed.rules.iter().any(|rule| rule.redex == *redex) {
+        continue
+      }
+      let rule = Rule { redex:redex.clone(), reduction:reduction.clone() };
+      completed.rules.push_back(rule.clone());
+      proposed.push(rule);
+    }
+    if proposed.len() == before {
+      break
+    }
+    remaining = confluence::analyse(&Evaluator::new(engine, &completed));
+  }
+  Completion { rules:proposed, rounds, remaining }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cmp::Ordering;
+
+  use mlatu_lib::{parse, Engine};
+
+  use super::{complete, Limits, Order};
+  use crate::library::tests::parsed;
+
+  #[test]
+  fn orients_each_divergence_into_a_rule() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a b = x. b c = y.");
+    let completion = complete(&engine, &library, Order::Size, &Limits::default());
+    let expected = parse::rules(&engine, "x c = a y.").expect("the rules parse");
+    assert_eq!(completion.rules, expected.into_iter().collect::<Vec<_>>());
+    assert_eq!(completion.rounds, 1);
+    assert!(completion.remaining.is_empty());
+  }
+
+  #[test]
+  fn compares_by_the_order_then_as_text() {
+    let engine = Engine::new();
+    let program = |text:&str| parse::terms(&engine, text).expect("the program parses");
+    let (nested, flat) = (program("((a b))"), program("a b"));
+    assert_eq!(Order::Size.compare(&engine, &nested, &flat), Ordering::Greater);
+    assert_eq!(Order::Length.compare(&engine, &nested, &flat), Ordering::Less);
+    assert_eq!(Order::Size.compare(&engine, &program("b"), &program("a")), Ordering::Greater);
+  }
+}
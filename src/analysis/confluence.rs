@@ -0,0 +1,122 @@
+//! Finding overlapping rules whose results depend on which of them is used.
+//!
+//! When a suffix of the redex of one rule is a prefix of the redex of
+//! another, the program made of both redexes sharing that overlap can be
+//! rewritten with either rule first. Such a critical pair joins if both
+//! results rewrite to the same normal form; otherwise which result a program
+//! gets depends on the matching order, and so on the order rules and files
+//! were loaded in.
+
+use im::Vector;
+use mlatu_lib::{Rule, Term};
+
+use super::LIMITS;
+use crate::eval::{Evaluator, Outcome};
+
+/// A program that two overlapping rules can both rewrite, by their indices
+/// in the rules that were checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CriticalPair {
+  /// The rule whose redex comes first in the program
+  pub first:usize,
+  /// The rule whose redex starts within the redex of the first
+  pub second:usize,
+  /// Both redexes, sharing the overlap
+  pub program:Vector<Term>,
+  /// The program rewritten once with the first rule
+  pub left:Vector<Term>,
+  /// The program rewritten once with the second rule
+  pub right:Vector<Term>,
+}
+
+/// A critical pair whose sides rewrite to different normal forms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+  pub pair:CriticalPair,
+  /// The normal form of the left side of the pair
+  pub left:Vector<Term>,
+  /// The normal form of the right side of the pair
+  pub right:Vector<Term>,
+}
+
+/// Every critical pair of some rules: for each pair of rules, including a
+/// rule with itself, each proper suffix of the first redex that is a proper
+/// prefix of the second. Rules with an empty redex are ignored, as they never
+/// match.
+#[must_use]
+pub fn critical_pairs(rules:&Vector<Rule>) -> Vec<CriticalPair> {
+  let mut pairs = Vec::new();
+  for (first, one) in rules.iter().enumerate() {
+    for (second, other) in rules.iter().enumerate() {
+      let longest = one.redex.len().min(other.redex.len());
+      for length in 1..longest {
+        let start = one.redex.len() - length;
+        if one.redex.clone().slice(start..) != other.redex.clone().slice(..length) {
+          continue
+        }
+        let rest = other.redex.clone().slice(length..);
+        let mut program = one.redex.clone();
+        program.append(rest.clone());
+        let mut left = one.reduction.clone();
+        left.append(rest);
+        let mut right = one.redex.clone().slice(..start);
+        right.append(other.reduction.clone());
+        pairs.push(CriticalPair { first, second, program, left, right });
+      }
+    }
+  }
+  pairs
+}
+
+/// The critical pairs of the library of an evaluator whose sides rewrite to
+/// different normal forms. Pairs with a side that does not reach a normal
+/// form within small limits are not reported, as nothing is known about
+/// them.
+#[must_use]
+pub fn analyse(evaluator:&Evaluator<'_>) -> Vec<Divergence> {
+  let mut divergences = Vec::new();
+  for pair in critical_pairs(&evaluator.library().rules) {
+    let left = evaluator.evaluate(&pair.left, &LIMITS);
+    let right = evaluator.evaluate(&pair.right, &LIMITS);
+    if left.outcome == Some(Outcome::Normal)
+       && right.outcome == Some(Outcome::Normal)
+       && left.terms != right.terms
+    {
+      divergences.push(Divergence { pair, left:left.terms, right:right.terms });
+    }
+  }
+  divergences
+}
+
+#[cfg(test)]
+mod tests {
+  use mlatu_lib::{parse, Engine};
+
+  use super::{analyse, critical_pairs, CriticalPair};
+  use crate::eval::Evaluator;
+  use crate::library::tests::parsed;
+
+  #[test]
+  fn finds_overlapping_redexes() {
+    let engine = Engine::new();
+    let program = |text:&str| parse::terms(&engine, text).expect("the program parses");
+    let library = parsed(&engine, "a b = x. b c = y. d = e.");
+    assert_eq!(critical_pairs(&library.rules), vec![CriticalPair { first:0,
+                                                                   second:1,
+                                                                   program:program("a b c"),
+                                                                   left:program("x c"),
+                                                                   right:program("a y") }]);
+    let divergences = analyse(&Evaluator::new(&engine, &library));
+    assert_eq!(divergences.len(), 1);
+    assert_eq!((divergences[0].left.clone(), divergences[0].right.clone()),
+               (program("x c"), program("a y")));
+  }
+
+  #[test]
+  fn reports_nothing_for_pairs_that_join() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a b = x. b c = y. x c = z. a y = z.");
+    assert_eq!(critical_pairs(&library.rules).len(), 1);
+    assert_eq!(analyse(&Evaluator::new(&engine, &library)), Vec::new());
+  }
+}
@@ -0,0 +1,201 @@
+//! Showing that rewriting with some rules terminates, or that a rule can make
+//! it loop.
+//!
+//! The redex of each rule is first rewritten on its own, and coming back to
+//! an earlier program proves that the rule can loop. Otherwise, a rule that
+//! cannot lead back to itself, through the rules that can use what its
+//! reduction produces, terminates, and so does a recursive group of rules in
+//! which every rule makes the program smaller, either in size or in the number
+//! of occurrences of the atoms the group rewrites. Reductions that copy or wrap
+//! quotes can make a program larger through the primitives, so they are never
+//! shown to terminate that way. A quote copied by one rule can be unwrapped by
+//! another and run again, so rules that copy and rules that unwrap are taken
+//! to lead to each other, and a rule that can lead to both is never shown to
+//! terminate.
+
+use std::collections::HashSet;
+
+use im::Vector;
+use mlatu_lib::{pretty, Engine, Term};
+
+use super::{atoms, components, dependencies, occurrences, LIMITS};
+use crate::eval::{primitives, size, Evaluator, Fired, Outcome, Primitive};
+use crate::pretty_terms;
+
+/// What is known about whether rewriting with a rule terminates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Termination {
+  /// The rule cannot keep rewriting forever, for the reason given
+  Terminates(String),
+  /// The rule can make rewriting loop, as explained
+  Loops(String),
+  /// Termination could not be shown, as explained
+  Unknown(String),
+}
+
+/// Whether a primitive appears in some terms, including inside quotes.
+fn uses(terms:&Vector<Term>, primitive:&Term) -> bool {
+  terms.iter().any(|term| match term {
+                | Term::Quote(terms) => uses(terms, primitive),
+                | _ => term == primitive,
+              })
+}
+
+/// The rules that can be reached from a rule, including itself.
+fn reachable(edges:&[Vec<usize>], from:usize) -> HashSet<usize> {
+  let mut found = HashSet::new();
+  let mut stack = vec![from];
+  while let Some(node) = stack.pop() {
+    if found.insert(node) {
+      stack.extend(&edges[node]);
+    }
+  }
+  found
+}
+
+/// Rewrites the redex of a rule on its own, which shows that the rule loops
+/// if rewriting comes back to an earlier program after using it.
+fn rewrite_redex(engine:&Engine, evaluator:&Evaluator<'_>, index:usize) -> Option<Termination> {
+  let redex = &evaluator.library().rules[index].redex;
+  if redex.is_empty() {
+    return Some(Termination::Terminates("its pattern is empty, so it never applies".to_string()))
+  }
+  let evaluation = evaluator.evaluate(redex, &LIMITS);
+  let pattern = pretty_terms(engine, redex.clone());
+  match evaluation.outcome {
+    | Some(Outcome::Cycle { start:_, length }) =>
+      evaluation.cycle()
+                .filter(|fired| fired.contains(&Fired::Rule(index)))
+                .map(|_| {
+                  Termination::Loops(format!("rewriting its pattern `{}` on its own comes back to \
+                                              `{}` every {} step(s), through {}",
+                                             pattern,
+                                             pretty_terms(engine, evaluation.terms.clone()),
+                                             length,
+                                             evaluator.describe_cycle(&evaluation)
+                                                      .unwrap_or_default()))
+                }),
+    | Some(Outcome::OutOfSteps | Outcome::TooLarge)
+      if evaluation.fired().contains(&Fired::Rule(index)) =>
+      Some(Termination::Unknown(format!("rewriting its pattern `{}` on its own {}",
+                                        pattern, evaluation))),
+    | _ => None,
+  }
+}
+
+/// Analyses each rule of the library of an evaluator, in order.
+#[must_use]
+pub fn analyse(engine:&Engine, evaluator:&Evaluator<'_>) -> Vec<Termination> {
+  let rules = &evaluator.library().rules;
+  let mut edges = dependencies(rules);
+  let primitive = |wanted:Primitive| {
+    primitives(engine).into_iter().find(|(_, primitive)| *primitive == wanted).map(|(term, _)| term)
+  };
+  let (copy, wrap, unwrap) =
+    (primitive(Primitive::Copy), primitive(Primitive::Wrap), primitive(Primitive::Unwrap));
+  let reduction_uses = |index:usize, term:&Option<Term>| {
+    term.as_ref().map_or(false, |term| uses(&rules[index].reduction, term))
+  };
+  let (copying, unwrapping):(Vec<_>, Vec<_>) =
+    ((0..rules.len()).filter(|index| reduction_uses(*index, &copy)).collect(),
+     (0..rules.len()).filter(|index| reduction_uses(*index, &unwrap)).collect());
+  for &index in &copying {
+    edges[index].extend(&unwrapping);
+  }
+  for &index in &unwrapping {
+    edges[index].extend(&copying);
+  }
+  let mut verdicts =
+    (0..rules.len()).map(|index| rewrite_redex(engine, evaluator, index)).collect::<Vec<_>>();
+  for component in components(&edges) {
+    let recursive = component.len() > 1 || edges[component[0]].contains(&component[0]);
+    let reach = reachable(&edges, component[0]);
+    let verdict = if recursive {
+      let group = component.iter()
+                           .map(|index| evaluator.describe(Fired::Rule(*index)))
+                           .collect::<Vec<_>>()
+                           .join(", ");
+      let copies = component.iter().any(|index| reduction_uses(*index, &copy));
+      let wraps = component.iter().any(|index| reduction_uses(*index, &wrap));
+      let mut defined = HashSet::new();
+      for index in &component {
+        atoms(&rules[*index].redex, &mut defined);
+      }
+      if !copies
+         && !wraps
+         && component.iter()
+                     .all(|index| size(&rules[*index].reduction) < size(&rules[*index].redex))
+      {
+        Termination::Terminates(format!("every rule of its recursive group ({}) makes the \
+                                         program smaller",
+                                        group))
+      } else if !copies
+                && component.iter().all(|index| {
+                                     occurrences(&rules[*index].reduction, &defined)
+                                     < occurrences(&rules[*index].redex, &defined)
+                                   })
+      {
+        Termination::Terminates(format!("every rule of its recursive group ({}) uses up more of \
+                                         the atoms the group rewrites than it produces",
+                                        group))
+      } else {
+        let mut names = defined.into_iter()
+                               .map(|atom| format!("`{}`", pretty::term(engine, atom)))
+                               .collect::<Vec<_>>();
+        names.sort();
+        Termination::Unknown(format!("it is recursive through {}, and neither the size of the \
+                                      program nor the number of {} decreases with every step",
+                                     group,
+                                     names.join(", ")))
+      }
+    } else if reach.iter().any(|index| copying.contains(index))
+              && reach.iter().any(|index| unwrapping.contains(index))
+    {
+      let reason = "it can lead to replacements that copy (`+`) and unwrap (`<`) quotes, which \
+                    can loop with the primitives alone";
+      Termination::Unknown(reason.to_string())
+    } else {
+      Termination::Terminates("it cannot lead back to itself".to_string())
+    };
+    for index in component {
+      if verdicts[index].is_none() {
+        verdicts[index] = Some(verdict.clone());
+      }
+    }
+  }
+  // Every rule is in a component, so every rule has a verdict by now
+  verdicts.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use mlatu_lib::Engine;
+
+  use super::{analyse, Termination};
+  use crate::eval::Evaluator;
+  use crate::library::tests::parsed;
+
+  #[test]
+  fn tells_terminating_rules_from_looping_ones() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = b. loop = loop. s s = s. d = (d) + <.");
+    let verdicts = analyse(&engine, &Evaluator::new(&engine, &library));
+    assert_eq!(verdicts[0], Termination::Terminates("it cannot lead back to itself".to_string()));
+    assert!(matches!(&verdicts[1], Termination::Loops(_)), "{:?}", verdicts[1]);
+    let smaller =
+      "every rule of its recursive group (rule 3 of rules.mlt) makes the program smaller";
+    assert_eq!(verdicts[2], Termination::Terminates(smaller.to_string()));
+    assert!(matches!(&verdicts[3], Termination::Unknown(_)), "{:?}", verdicts[3]);
+  }
+
+  #[test]
+  fn does_not_trust_copying_and_unwrapping_in_separate_rules() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "dup = +. run = <. go = dup run. a = b.");
+    let verdicts = analyse(&engine, &Evaluator::new(&engine, &library));
+    for verdict in &verdicts[..3] {
+      assert!(matches!(verdict, Termination::Unknown(_)), "{:?}", verdict);
+    }
+    assert_eq!(verdicts[3], Termination::Terminates("it cannot lead back to itself".to_string()));
+  }
+}
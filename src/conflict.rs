@@ -0,0 +1,109 @@
+//! Finding rules that conflict with or shadow each other.
+//!
+//! Rewriting always uses the longest rule that matches, so two rules with
+//! the same redex make the result depend on the order the rules were loaded
+//! in, and a rule whose redex starts with the whole redex of another rule
+//! takes precedence over it wherever both match.
+
+use std::collections::HashMap;
+
+use im::Vector;
+use mlatu_lib::{Rule, Term};
+
+/// How two rules interact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overlap {
+  /// The rules have the same redex but different reductions, and the first
+  /// rule was loaded before the second
+  Ambiguous,
+  /// The rules are identical, and the first rule was loaded before the
+  /// second
+  Duplicate,
+  /// The redex of the first rule is a proper prefix of the redex of the
+  /// second, so the second is used instead of the first wherever both match
+  Shadowed,
+}
+
+/// Two rules that overlap, by their indices in the rules that were checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conflict {
+  pub overlap:Overlap,
+  pub first:usize,
+  pub second:usize,
+}
+
+fn is_prefix(prefix:&Vector<Term>, terms:&Vector<Term>) -> bool {
+  prefix.len() < terms.len() && prefix.iter().zip(terms.iter()).all(|(a, b)| a == b)
+}
+
+/// Finds every pair of overlapping rules. Rules with an empty redex are
+/// ignored, as they never match.
+#[must_use]
+pub fn conflicts(rules:&Vector<Rule>) -> Vec<Conflict> {
+  let mut found = Vec::new();
+  let mut seen = HashMap::new();
+  for (second, rule) in rules.iter().enumerate() {
+    if rule.redex.is_empty() {
+      continue
+    }
+    if let Some(&first) = seen.get(&rule.redex) {
+      let overlap = if rules[first] == *rule { Overlap::Duplicate } else { Overlap::Ambiguous };
+      found.push(Conflict { overlap, first, second });
+    } else {
+      let _previous = seen.insert(rule.redex.clone(), second);
+    }
+  }
+  // Only rules whose redexes start with the same term can be prefixes of
+  // each other
+  let mut starting = HashMap::<_, Vec<_>>::new();
+  for (index, rule) in rules.iter().enumerate() {
+    if let Some(first) = rule.redex.front() {
+      starting.entry(first).or_default().push(index);
+    }
+  }
+  for (i, shorter) in rules.iter().enumerate() {
+    if shorter.redex.is_empty() || seen.get(&shorter.redex) != Some(&i) {
+      continue
+    }
+    for &j in starting.get(&shorter.redex[0]).into_iter().flatten() {
+      if is_prefix(&shorter.redex, &rules[j].redex) {
+        found.push(Conflict { overlap:Overlap::Shadowed, first:i, second:j });
+      }
+    }
+  }
+  found.sort_by_key(|conflict| conflict.first.max(conflict.second));
+  found
+}
+
+#[cfg(test)]
+mod tests {
+  use mlatu_lib::{parse, Engine};
+
+  use super::{conflicts, Overlap};
+
+  /// The overlap and indices of each conflict between some rules.
+  fn found(rules:&str) -> Vec<(Overlap, usize, usize)> {
+    let engine = Engine::new();
+    let rules = parse::rules(&engine, rules).expect("the rules parse");
+    conflicts(&rules).into_iter()
+                     .map(|conflict| (conflict.overlap, conflict.first, conflict.second))
+                     .collect()
+  }
+
+  #[test]
+  fn finds_ambiguous_rules() {
+    assert_eq!(found("a b = x. c = y. a b = z."), vec![(Overlap::Ambiguous, 0, 2)]);
+  }
+
+  #[test]
+  fn finds_duplicate_rules() {
+    assert_eq!(found("a b = x. a b = x. = y."), vec![(Overlap::Duplicate, 0, 1)]);
+  }
+
+  #[test]
+  fn finds_shadowed_rules() {
+    assert_eq!(found("a b c = x. b = y. a = z. a b = w."), vec![(Overlap::Shadowed, 2, 0),
+                                                                (Overlap::Shadowed, 2, 3),
+                                                                (Overlap::Shadowed, 3, 0)]);
+  }
+}
@@ -0,0 +1,146 @@
+use std::path::{Path, PathBuf};
+
+use mlatu_lib::Engine;
+
+use crate::diagnostic::{Diagnostic, Error, Kind};
+use crate::format::{Contents, Format};
+use crate::read_file;
+
+/// The contents of a file without the locations of its directives and rules,
+/// which a conversion need not preserve.
+fn without_spans(mut contents:Contents) -> Contents {
+  for include in &mut contents.includes {
+    include.span = None;
+  }
+  contents.rule_spans.clear();
+  contents
+}
+
+/// Writes the contents of a file in another format and reads them back.
+/// Include directives are kept; when a whole directory is converted, included
+/// paths with the extension of a rule file are changed to point at the
+/// converted files instead.
+fn write_file(engine:&Engine, mut contents:Contents, input:&Path, output:&Path, format:Format,
+              in_dir:bool)
+              -> Result<(), Error> {
+  if in_dir {
+    for include in &mut contents.includes {
+      let path = Path::new(&include.path);
+      if Format::from_path(path).is_some() {
+        include.path = path.with_extension(format.extension()).to_string_lossy().into_owned();
+      }
+    }
+  }
+  std::fs::write(output, format.write(engine, contents.clone())).map_err(|e| {
+    Diagnostic::new(Kind::Other, format!("could not write file: {}", e)).with_path(output)
+  })?;
+  if without_spans(read_file(engine, output)?.0) == without_spans(contents) {
+    Ok(())
+  } else {
+    Err(format!("converting '{}' to '{}' did not preserve its rules",
+                input.display(),
+                output.display()).into())
+  }
+}
+
+fn create_dir(path:&Path) -> Result<(), Error> {
+  std::fs::create_dir_all(path).map_err(|e| {
+                                 Diagnostic::new(Kind::Other,
+                                                 format!("could not create directory: {}", e))
+                                 .with_path(path)
+                                 .into()
+                               })
+}
+
+/// Finds every file in a directory tree with the extension of a rule file,
+/// along with the file it is converted to, skipping the directory
+/// `output_root` so that an output directory inside the input is not
+/// converted again.
+fn find_files(input:&Path, output:&Path, output_root:&Path, format:Format,
+              found:&mut Vec<(PathBuf, PathBuf)>)
+              -> Result<(), Error> {
+  let read_error =
+    |e| Diagnostic::new(Kind::Read, format!("could not read directory: {}", e)).with_path(input);
+  let mut entries =
+    std::fs::read_dir(input).map_err(read_error)?
+                            .map(|entry| entry.map(|entry| (entry.path(), entry.file_name())))
+                            .collect::<Result<Vec<_>, _>>()
+                            .map_err(read_error)?;
+  entries.sort();
+  for (path, name) in entries {
+    if path.is_dir() {
+      if path.canonicalize().map_or(false, |path| path == output_root) {
+        continue
+      }
+      find_files(&path, &output.join(name), output_root, format, found)?;
+    } else if Format::from_path(&path).is_some() {
+      let target = output.join(name).with_extension(format.extension());
+      found.push((path, target));
+    }
+  }
+  Ok(())
+}
+
+/// Converts a rule file, or every rule file in a directory tree, to another
+/// format.
+///
+/// The output format is `to` if given, or else the extension of `output`.
+/// Every converted file is read back to check that its rules survived the
+/// conversion unchanged, along with its directives. Returns the pairs
+/// of files that were converted, and when converting a directory, a warning
+/// for each file that was skipped because it could not be loaded, such as a
+/// `.json` file that does not hold rules. Every file is loaded before any is
+/// written. An output directory inside the input directory is left out of
+/// the conversion.
+///
+/// # Errors
+///
+/// Returns `Err` if the output format could not be determined, if a single
+/// file to convert could not be loaded, or if any file could not be written
+/// or read back with the same rules
+pub fn convert(engine:&Engine, input:&Path, output:&Path, to:Option<Format>)
+               -> Result<(Vec<(PathBuf, PathBuf)>, Vec<Diagnostic>), Error> {
+  if input.is_dir() {
+    let format =
+      to.ok_or_else(|| "an output format must be given when converting a directory".to_string())?;
+    create_dir(output)?;
+    let output_root = output.canonicalize().map_err(|e| {
+                                              Diagnostic::new(Kind::Read,
+                                                              format!("could not resolve \
+                                                                       directory: {}",
+                                                                      e)).with_path(output)
+                                            })?;
+    let mut found = Vec::new();
+    find_files(input, output, &output_root, format, &mut found)?;
+    let mut loaded = Vec::new();
+    let mut warnings = Vec::new();
+    for (path, target) in found {
+      match read_file(engine, &path) {
+        | Ok((contents, ..)) => loaded.push((path, target, contents)),
+        | Err(error) => {
+          let skipped =
+            |diagnostic:Diagnostic| diagnostic.as_warning().with_note("the file was skipped");
+          warnings.extend(error.diagnostics.into_iter().map(skipped));
+        },
+      }
+    }
+    let mut converted = Vec::new();
+    for (path, target, contents) in loaded {
+      if let Some(parent) = target.parent() {
+        create_dir(parent)?;
+      }
+      write_file(engine, contents, &path, &target, format, true)?;
+      converted.push((path, target));
+    }
+    Ok((converted, warnings))
+  } else {
+    let format = to.or_else(|| Format::from_path(output)).ok_or_else(|| {
+                                                            format!("cannot tell the format of \
+                                                                     '{}' from its extension",
+                                                                    output.display())
+                                                          })?;
+    let (contents, ..) = read_file(engine, input)?;
+    write_file(engine, contents, input, output, format, false)?;
+    Ok((vec![(input.to_path_buf(), output.to_path_buf())], Vec::new()))
+  }
+}
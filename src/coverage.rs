@@ -0,0 +1,210 @@
+//! Counting how often each loaded rule fires, to find the rules tests never
+//! use.
+//!
+//! Rules are keyed by where they were defined, so a file loaded for several
+//! test files is counted once, however many libraries it was loaded into.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use im::vector;
+use mlatu_lib::{pretty, Engine};
+
+use crate::eval::{Evaluation, Fired};
+use crate::Library;
+
+/// How a coverage report is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  /// A table of the rules of each file and how often they fired
+  Table,
+  /// The lcov tracefile format, with a function record for each rule
+  Lcov,
+}
+
+impl Format {
+  /// Looks up a coverage format by name.
+  #[must_use]
+  pub fn from_name(name:&str) -> Option<Self> {
+    match name {
+      | "table" => Some(Self::Table),
+      | "lcov" => Some(Self::Lcov),
+      | _ => None,
+    }
+  }
+}
+
+struct Entry {
+  /// The first line of the rule in text files
+  line:Option<usize>,
+  /// The pretty-printed rule
+  rule:String,
+  firings:usize,
+}
+
+/// How often each rule of the loaded files fired.
+#[derive(Default)]
+pub struct Coverage {
+  /// The rules of each file, by their index in the file
+  files:BTreeMap<PathBuf, BTreeMap<usize, Entry>>,
+}
+
+impl Coverage {
+  /// Adds the rules of a library that have not been seen yet, as never having
+  /// fired.
+  pub fn add_library(&mut self, engine:&Engine, library:&Library) {
+    for (index, rule) in library.rules.iter().enumerate() {
+      if let Some(origin) = library.origin(index) {
+        let _entry = self.files
+                         .entry(origin.path.clone())
+                         .or_default()
+                         .entry(origin.index)
+                         .or_insert_with(|| {
+                           Entry { line:origin.lines.as_ref().map(|lines| *lines.start()),
+                                   rule:pretty::rules(engine, vector![rule.clone()]).trim_end()
+                                                                                    .to_string(),
+                                   firings:0 }
+                         });
+      }
+    }
+  }
+
+  /// Counts the rules that fired in an evaluation with the rules of a library.
+  pub fn record(&mut self, library:&Library, evaluation:&Evaluation) {
+    for fired in evaluation.fired() {
+      if let Fired::Rule(index) = fired {
+        if let Some(entry) =
+          library.origin(*index)
+                 .and_then(|origin| self.files.get_mut(&origin.path)?.get_mut(&origin.index))
+        {
+          entry.firings += 1;
+        }
+      }
+    }
+  }
+
+  /// Prints the report.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Err` if the report could not be written
+  pub fn write(&self, format:Format, out:&mut impl Write) -> io::Result<()> {
+    match format {
+      | Format::Table => self.write_table(out),
+      | Format::Lcov => self.write_lcov(out),
+    }
+  }
+
+  fn write_table(&self, out:&mut impl Write) -> io::Result<()> {
+    let (mut total, mut hit) = (0, 0);
+    for (path, entries) in &self.files {
+      writeln!(out, "{}", path.display())?;
+      writeln!(out, "  {:>5} {:>5} {:>8}  rule", "line", "rule", "firings")?;
+      for (index, entry) in entries {
+        let line = entry.line.map_or_else(|| "-".to_string(), |line| line.to_string());
+        writeln!(out, "  {:>5} {:>5} {:>8}  {}", line, index + 1, entry.firings, entry.rule)?;
+      }
+      let fired = entries.values().filter(|entry| entry.firings > 0).count();
+      writeln!(out,
+               "  {} of {} rule(s) fired ({}%)",
+               fired,
+               entries.len(),
+               percent(fired, entries.len()))?;
+      total += entries.len();
+      hit += fired;
+    }
+    writeln!(out, "total: {} of {} rule(s) fired ({}%)", hit, total, percent(hit, total))
+  }
+
+  fn write_lcov(&self, out:&mut impl Write) -> io::Result<()> {
+    for (path, entries) in &self.files {
+      writeln!(out, "TN:")?;
+      writeln!(out, "SF:{}", path.display())?;
+      for (index, entry) in entries {
+        writeln!(out, "FN:{},rule {}", entry.line.unwrap_or(0), index + 1)?;
+      }
+      for (index, entry) in entries {
+        writeln!(out, "FNDA:{},rule {}", entry.firings, index + 1)?;
+      }
+      let fired = entries.values().filter(|entry| entry.firings > 0).count();
+      writeln!(out, "FNF:{}", entries.len())?;
+      writeln!(out, "FNH:{}", fired)?;
+      // Several rules can start on the same line
+      let mut lines = BTreeMap::new();
+      for entry in entries.values() {
+        if let Some(line) = entry.line {
+          *lines.entry(line).or_insert(0) += entry.firings;
+        }
+      }
+      for (line, firings) in &lines {
+        writeln!(out, "DA:{},{}", line, firings)?;
+      }
+      writeln!(out, "LF:{}", lines.len())?;
+      writeln!(out, "LH:{}", lines.values().filter(|firings| **firings > 0).count())?;
+      writeln!(out, "end_of_record")?;
+    }
+    Ok(())
+  }
+}
+
+/// A percentage, rounded down, of 100 for nothing out of nothing.
+fn percent(part:usize, whole:usize) -> usize { (part * 100).checked_div(whole).unwrap_or(100) }
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use mlatu_lib::{parse, Engine};
+
+  use super::{percent, Coverage, Format};
+  use crate::eval::{Evaluator, Limits};
+  use crate::format::text;
+  use crate::library::tests::parsed;
+  use crate::Library;
+
+  fn report(coverage:&Coverage, format:Format) -> String {
+    let mut out = Vec::new();
+    coverage.write(format, &mut out).expect("the report is written");
+    String::from_utf8(out).expect("the report is UTF-8")
+  }
+
+  #[test]
+  fn counts_firings_by_rule() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = x. b = y. c = z.");
+    let mut coverage = Coverage::default();
+    coverage.add_library(&engine, &library);
+    let terms = parse::terms(&engine, "a a b").expect("the program parses");
+    let evaluation = Evaluator::new(&engine, &library).evaluate(&terms, &Limits::default());
+    coverage.record(&library, &evaluation);
+    assert_eq!(report(&coverage, Format::Lcov),
+               "TN:\nSF:rules.mlt\nFN:0,rule 1\nFN:0,rule 2\nFN:0,rule 3\nFNDA:2,rule \
+                1\nFNDA:1,rule 2\nFNDA:0,rule 3\nFNF:3\nFNH:2\nLF:0\nLH:0\nend_of_record\n");
+    assert!(report(&coverage, Format::Table).ends_with("total: 2 of 3 rule(s) fired (66%)\n"));
+  }
+
+  #[test]
+  fn reports_the_lines_rules_start_on() {
+    let engine = Engine::new();
+    let source = "a = x.\nb =\n  y.\nc = a.\nd = z. e = z.\n";
+    let contents = text::parse(&engine, source).expect("the rules parse");
+    let mut library = Library::default();
+    library.add(&engine, Path::new("rules.mlt"), source, contents).expect("the rules load");
+    let mut coverage = Coverage::default();
+    coverage.add_library(&engine, &library);
+    let terms = parse::terms(&engine, "a c b d").expect("the program parses");
+    let evaluation = Evaluator::new(&engine, &library).evaluate(&terms, &Limits::default());
+    coverage.record(&library, &evaluation);
+    assert_eq!(report(&coverage, Format::Lcov),
+               "TN:\nSF:rules.mlt\nFN:1,rule 1\nFN:2,rule 2\nFN:4,rule 3\nFN:5,rule 4\nFN:5,rule \
+                5\nFNDA:2,rule 1\nFNDA:1,rule 2\nFNDA:1,rule 3\nFNDA:1,rule 4\nFNDA:0,rule \
+                5\nFNF:5\nFNH:4\nDA:1,2\nDA:2,1\nDA:4,1\nDA:5,1\nLF:4\nLH:4\nend_of_record\n");
+  }
+
+  #[test]
+  fn counts_nothing_out_of_nothing_as_everything() {
+    assert_eq!(percent(0, 0), 100);
+    assert_eq!(percent(1, 3), 33);
+  }
+}
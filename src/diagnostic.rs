@@ -0,0 +1,256 @@
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::library::Origin;
+
+/// What went wrong, which decides the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+  /// A file could not be read
+  Read,
+  /// A file could not be decoded (invalid UTF-8, or malformed binary or JSON
+  /// data)
+  Decode,
+  /// Rules or a program could not be parsed
+  Parse,
+  /// Loaded rules conflict with or shadow each other
+  Conflict,
+  /// Rewriting a program did not terminate within the limits
+  Limit,
+  /// Tests did not pass
+  Test,
+  /// Analysing rules found a problem with them
+  Analysis,
+  /// Any other failure
+  Other,
+}
+
+impl Kind {
+  /// The exit code of a process that failed for this reason.
+  #[must_use]
+  pub const fn exit_code(self) -> i32 {
+    match self {
+      | Self::Other => 1,
+      | Self::Read => 3,
+      | Self::Decode => 4,
+      | Self::Parse => 5,
+      | Self::Conflict => 6,
+      | Self::Limit => 7,
+      | Self::Test => 8,
+      | Self::Analysis => 9,
+    }
+  }
+}
+
+/// A location in a source text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+  /// The byte range in the source
+  pub bytes:Range<usize>,
+  /// The line the span starts on, counting from 1
+  pub line:usize,
+  /// The column the span starts at in characters, counting from 1
+  pub column:usize,
+  line_text:String,
+  width:usize,
+}
+
+impl Span {
+  /// Locates a byte range in a source text.
+  #[must_use]
+  pub fn new(source:&str, bytes:Range<usize>) -> Self {
+    let mut start = bytes.start.min(source.len());
+    while !source.is_char_boundary(start) {
+      start -= 1;
+    }
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+    let line = source[..start].matches('\n').count() + 1;
+    let column = source[line_start..start].chars().count() + 1;
+    let line_text = source[line_start..line_end].trim_end_matches('\r').to_string();
+    let mut end = bytes.end.clamp(start, line_end);
+    while !source.is_char_boundary(end) {
+      end += 1;
+    }
+    let width = source[start..end].chars().count().max(1);
+    Self { bytes, line, column, line_text, width }
+  }
+}
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+/// An error located in a file or source text, displayed in the style of
+/// rustc with the offending source line and a caret under the bad token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+  pub kind:Kind,
+  pub severity:Severity,
+  pub message:String,
+  pub path:Option<PathBuf>,
+  pub span:Option<Span>,
+  /// Extra context, such as the chain of includes that led to a file
+  pub notes:Vec<String>,
+}
+
+impl Diagnostic {
+  #[must_use]
+  pub fn new(kind:Kind, message:impl Into<String>) -> Self {
+    Self { kind,
+           severity:Severity::Error,
+           message:message.into(),
+           path:None,
+           span:None,
+           notes:Vec::new() }
+  }
+
+  /// Makes this diagnostic a warning, which is reported but does not stop
+  /// anything from running.
+  #[must_use]
+  pub const fn as_warning(mut self) -> Self {
+    self.severity = Severity::Warning;
+    self
+  }
+
+  #[must_use]
+  pub fn with_path(mut self, path:&Path) -> Self {
+    self.path = Some(path.to_path_buf());
+    self
+  }
+
+  #[must_use]
+  pub fn with_span(mut self, source:&str, bytes:Range<usize>) -> Self {
+    self.span = Some(Span::new(source, bytes));
+    self
+  }
+
+  /// Locates this diagnostic at a loaded rule.
+  #[must_use]
+  pub fn with_origin(mut self, origin:&Origin) -> Self {
+    self.path = Some(origin.path.clone());
+    self.span.clone_from(&origin.span);
+    self
+  }
+
+  #[must_use]
+  pub fn with_note(mut self, note:impl Into<String>) -> Self {
+    self.notes.push(note.into());
+    self
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.severity {
+      | Severity::Error => write!(f, "error: {}", self.message)?,
+      | Severity::Warning => write!(f, "warning: {}", self.message)?,
+    }
+    let gutter = self.span.as_ref().map_or(1, |span| span.line.to_string().len());
+    match (&self.path, &self.span) {
+      | (Some(path), Some(span)) => write!(f,
+                                           "\n{:gutter$}--> {}:{}:{}",
+                                           "",
+                                           path.display(),
+                                           span.line,
+                                           span.column,
+                                           gutter = gutter)?,
+      | (Some(path), None) => write!(f, "\n{:gutter$}--> {}", "", path.display(), gutter = gutter)?,
+      | (None, Some(span)) =>
+        write!(f, "\n{:gutter$}--> {}:{}", "", span.line, span.column, gutter = gutter)?,
+      | (None, None) => {},
+    }
+    if let Some(span) = &self.span {
+      write!(f, "\n{:gutter$} |", "", gutter = gutter)?;
+      write!(f, "\n{} | {}", span.line, span.line_text)?;
+      write!(f,
+             "\n{:gutter$} | {:indent$}{}",
+             "",
+             "",
+             "^".repeat(span.width),
+             gutter = gutter,
+             indent = span.column - 1)?;
+    }
+    for note in &self.notes {
+      write!(f, "\n{:gutter$} = note: {}", "", note, gutter = gutter)?;
+    }
+    Ok(())
+  }
+}
+
+/// One or more diagnostics, e.g. every error found while loading some rule
+/// files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+  pub diagnostics:Vec<Diagnostic>,
+}
+
+impl Error {
+  /// The exit code for the first diagnostic.
+  #[must_use]
+  pub fn exit_code(&self) -> i32 {
+    self.diagnostics.first().map_or(Kind::Other, |diagnostic| diagnostic.kind).exit_code()
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+    for (i, diagnostic) in self.diagnostics.iter().enumerate() {
+      if i > 0 {
+        writeln!(f)?;
+      }
+      write!(f, "{}", diagnostic)?;
+    }
+    Ok(())
+  }
+}
+
+impl From<Diagnostic> for Error {
+  fn from(diagnostic:Diagnostic) -> Self { Self { diagnostics:vec![diagnostic] } }
+}
+
+impl From<Vec<Diagnostic>> for Error {
+  fn from(diagnostics:Vec<Diagnostic>) -> Self { Self { diagnostics } }
+}
+
+impl From<String> for Error {
+  fn from(message:String) -> Self { Diagnostic::new(Kind::Other, message).into() }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use super::{Diagnostic, Kind, Span};
+
+  #[test]
+  fn spans_count_lines_and_characters() {
+    let span = Span::new("a = b.\nc = ) d.\n", 11..12);
+    assert_eq!((span.line, span.column, span.width), (2, 5, 1));
+    let span = Span::new("αβ = γ.\n", 7..9);
+    assert_eq!((span.line, span.column, span.width), (1, 6, 1));
+    // Spans are clipped to the line they start on
+    let span = Span::new("a = b.\nc = d.\n", 4..100);
+    assert_eq!((span.line, span.column, span.width, span.line_text.as_str()), (1, 5, 2, "a = b."));
+    let span = Span::new("a = b", 5..5);
+    assert_eq!((span.line, span.column, span.width), (1, 6, 1));
+  }
+
+  #[test]
+  fn renders_the_line_and_a_caret() {
+    let diagnostic =
+      Diagnostic::new(Kind::Parse, "unmatched `)`").with_path(Path::new("x.mlt"))
+                                                   .with_span("a = b.\nc = ) d.\n", 11..12)
+                                                   .with_note("a note");
+    assert_eq!(diagnostic.to_string(),
+               "error: unmatched `)`\n --> x.mlt:2:5\n  |\n2 | c = ) d.\n  |     ^\n  = note: a \
+                note");
+    let warning =
+      Diagnostic::new(Kind::Conflict, "shadowed").as_warning().with_span("x y = z.", 0..3);
+    assert_eq!(warning.to_string(), "warning: shadowed\n --> 1:1\n  |\n1 | x y = z.\n  | ^^^");
+  }
+}
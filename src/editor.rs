@@ -1,24 +1,36 @@
 use std::io::{stdout, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use crossterm::event::KeyCode::{Backspace, Char, Delete, Down, Esc, Left, Right, Up};
-use crossterm::event::KeyModifiers;
+use crossterm::event::KeyCode::{Backspace, Char, Delete, Down, Enter, Esc, Left, Right, Up};
+use crossterm::event::{KeyEvent, KeyModifiers};
 use crossterm::queue;
 use im::{vector, Vector};
 use mlatu_lib::{parse, pretty, Engine, Rule, Term};
 use tokio::sync::RwLock;
 
+use crate::format::{Contents, Format};
+use crate::library::Origin;
+use crate::read_file;
 use crate::view::{State, View};
 
 pub struct Editor {
   engine:Engine,
   path:PathBuf,
+  format:Format,
+  /// The directives of the file, which are kept as they are; its rules are
+  /// edited separately
+  header:Contents,
   view:View,
   rules:Vec<Arc<RwLock<Rule>>>,
+  /// Where each rule is in the file as last read or saved, or `None` for new
+  /// rules
+  origins:Vec<Option<Origin>>,
   rule_idx:usize,
   should_quit:bool,
   state:State,
+  /// Why the rules could not be saved, if the last save failed
+  failure:Option<String>,
 }
 
 fn die(e:&str) {
@@ -28,11 +40,26 @@ fn die(e:&str) {
   panic!("{}", e)
 }
 
+/// The status line for a rule, e.g. `nat.mlt:12 (rule 3/10)`.
+fn status(path:&Path, origin:Option<&Origin>, index:usize, count:usize) -> String {
+  match origin.and_then(|origin| origin.lines.as_ref()) {
+    | Some(lines) =>
+      format!("{}:{} (rule {}/{})", path.to_string_lossy(), lines.start(), index + 1, count),
+    | None => format!("{} (rule {}/{})", path.to_string_lossy(), index + 1, count),
+  }
+}
+
 impl Editor {
+  /// Creates an editor for the contents of a file, given the origins of its
+  /// rules.
+  ///
   /// # Errors
   ///
   /// Will return `Err` if there was an error constructing a terminal
-  pub fn new(engine:Engine, path:PathBuf, original_rules:Vector<Rule>) -> Result<Self, String> {
+  pub fn new(engine:Engine, path:PathBuf, format:Format, contents:Contents, origins:Vec<Origin>)
+             -> Result<Self, String> {
+    let original_rules = contents.rules.clone();
+    let header = Contents { rules:Vector::new(), ..contents };
     crossterm::terminal::enable_raw_mode().map_err(|e| e.to_string())?;
     let should_quit = false;
     let rule_idx = 0;
@@ -47,12 +74,23 @@ impl Editor {
       }
       (rules, state)
     };
-    let default_status =
-      format!("{} (rule {}/{})", path.to_string_lossy(), rule_idx + 1, rules.len());
+    let mut origins = origins.into_iter().map(Some).collect::<Vec<_>>();
+    origins.resize(rules.len(), None);
+    let default_status = status(&path, origins[rule_idx].as_ref(), rule_idx, rules.len());
     let view = View::new(Arc::clone(&rules[rule_idx]),
                          ("| Pattern |".to_string(), "| Replacement |".to_string()),
                          default_status).map_err(|e| e.to_string())?;
-    Ok(Self { engine, path, view, rules, rule_idx, should_quit, state })
+    Ok(Self { engine,
+              path,
+              format,
+              header,
+              view,
+              rules,
+              origins,
+              rule_idx,
+              should_quit,
+              state,
+              failure:None })
   }
 
   pub async fn run(&mut self) {
@@ -75,19 +113,55 @@ impl Editor {
     }
   }
 
-  async fn save(&mut self) -> Result<(), String> {
+  /// Writes the rules to a file in a format, saving there from now on if
+  /// that worked. A failure is shown in the status line, and the file the
+  /// editor saves to stays the same.
+  async fn save_to(&mut self, path:PathBuf, format:Format) {
     let mut rs = Vector::new();
     for rule in &self.rules {
       let guard = rule.read().await;
       rs.push_back(guard.clone());
     }
-    std::fs::write(self.path.clone(), &pretty::rules(&self.engine, rs)).map_err(|e| e.to_string())
+    let bytes = format.write(&self.engine, Contents { rules:rs, ..self.header.clone() });
+    match std::fs::write(&path, bytes) {
+      | Ok(()) => {
+        self.path = path;
+        self.format = format;
+        self.failure = None;
+        // Rules may have moved, so find out where they are now
+        if let Ok((_, _, origins)) = read_file(&self.engine, &self.path) {
+          self.origins = origins.into_iter().map(Some).collect();
+          self.origins.resize(self.rules.len(), None);
+        }
+      },
+      | Err(e) => self.failure = Some(format!("could not save to {}: {}", path.display(), e)),
+    }
+    self.view.set_default_status(self.default_status());
+  }
+
+  async fn save(&mut self) { self.save_to(self.path.clone(), self.format).await }
+
+  /// Saves to another file from now on, in the format given by its
+  /// extension, or in the current format if the extension is not known.
+  async fn save_as(&mut self, path:PathBuf) {
+    let format = Format::from_path(&path).unwrap_or(self.format);
+    self.save_to(path, format).await
+  }
+
+  fn default_status(&self) -> String {
+    let status = status(&self.path,
+                        self.origins.get(self.rule_idx).and_then(Option::as_ref),
+                        self.rule_idx,
+                        self.rules.len());
+    match &self.failure {
+      | Some(failure) => format!("{} ({})", status, failure),
+      | None => status,
+    }
   }
 
   async fn set_left_view(&mut self, index:usize) -> Result<(), String> {
     let rule = &self.rules[self.rule_idx];
-    let default_status =
-      format!("{} (rule {}/{})", self.path.to_string_lossy(), self.rule_idx + 1, self.rules.len());
+    let default_status = self.default_status();
     let guard = rule.read().await;
     self.state = if guard.redex.is_empty() {
       State::AtLeft
@@ -102,8 +176,7 @@ impl Editor {
 
   async fn set_right_view(&mut self, index:usize) -> Result<(), String> {
     let rule = &self.rules[self.rule_idx];
-    let default_status =
-      format!("{} (rule {}/{})", self.path.to_string_lossy(), self.rule_idx + 1, self.rules.len());
+    let default_status = self.default_status();
     let guard = rule.read().await;
     self.state = if guard.reduction.is_empty() {
       State::AtRight
@@ -272,25 +345,58 @@ impl Editor {
     };
   }
 
+  async fn remove_rule(&mut self) -> Result<(), String> {
+    if self.rules.len() == 1 {
+      self.rules[0] = Arc::new(RwLock::new(Rule { redex:Vector::new(), reduction:Vector::new() }));
+      self.origins[0] = None;
+    } else {
+      self.rules.remove(self.rule_idx);
+      self.origins.remove(self.rule_idx);
+      self.rule_idx = self.rule_idx.min(self.rules.len() - 1);
+    }
+    self.set_left_view(0).await
+  }
+
+  async fn process_save_as(&mut self, event:KeyEvent, path:String, state:Box<State>)
+                           -> Result<(), String> {
+    match event.code {
+      | Enter => {
+        self.state = *state;
+        self.save_as(PathBuf::from(path)).await;
+      },
+      | Esc => self.state = *state,
+      | Backspace | Delete => {
+        let mut path = path;
+        path.pop();
+        self.state = State::SavingAs(path, state);
+      },
+      | Char(c) => {
+        let mut path = path;
+        path.push(c);
+        self.state = State::SavingAs(path, state);
+      },
+      | _ => {},
+    }
+    Ok(())
+  }
+
   async fn process_keypress(&mut self) -> Result<(), String> {
     let event = self.view.read_key().await.map_err(|e| e.to_string())?;
+    if let State::SavingAs(path, state) = self.state.clone() {
+      return self.process_save_as(event, path, state).await
+    }
     match (event.code, event.modifiers) {
       | (Esc, _) => self.should_quit = true,
-      | (Char('w'), KeyModifiers::CONTROL) => self.save().await?,
-      | (Char('r'), KeyModifiers::CONTROL) => {
-        if self.rules.len() == 1 {
-          self.rules[0] =
-            Arc::new(RwLock::new(Rule { redex:Vector::new(), reduction:Vector::new() }));
-        } else {
-          self.rules.remove(self.rule_idx);
-          self.rule_idx = self.rule_idx.min(self.rules.len() - 1);
-        }
-        self.set_left_view(0).await?;
-      },
+      | (Char('w'), KeyModifiers::CONTROL) => self.save().await,
+      | (Char('s'), KeyModifiers::CONTROL) =>
+        self.state =
+          State::SavingAs(self.path.to_string_lossy().into_owned(), Box::new(self.state.clone())),
+      | (Char('r'), KeyModifiers::CONTROL) => self.remove_rule().await?,
       | (Char(' '), KeyModifiers::CONTROL) => {
         self.rules
             .insert(self.rule_idx,
                     Arc::new(RwLock::new(Rule { redex:Vector::new(), reduction:Vector::new() })));
+        self.origins.insert(self.rule_idx, None);
         self.set_left_view(0).await?;
       },
       | (Char(c), _) => match (c, self.state.clone()) {
@@ -0,0 +1,642 @@
+//! Rewriting one step at a time.
+//!
+//! [`mlatu_lib::rewrite`] only gives the normal form of a program. The
+//! [`Evaluator`] follows the same evaluation order by default, using the
+//! longest rule that matches furthest to the left, but exposes every step so
+//! that they can be traced. Other orders can be chosen with a [`Strategy`],
+//! so that the same program can be compared under each of them.
+//!
+//! Rewriting need not terminate, so an [`Evaluation`] can be advanced a
+//! slice at a time within [`Limits`] on the number of steps, the size of the
+//! program and the time taken, and cancelled between slices. The hash of
+//! every program an evaluation passes through is remembered, so that one
+//! which comes back to an earlier program stops as soon as the cycle is
+//! complete.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+use im::{vector, Vector};
+use mlatu_lib::{parse, Engine, Term};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::Library;
+
+/// One of the six primitives, which act on the quotes before them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Primitive {
+  /// `x+` rewrites to `x x`
+  Copy,
+  /// `x-` rewrites to nothing
+  Remove,
+  /// `x>` rewrites to `(x)`
+  Wrap,
+  /// `(x)<` rewrites to `x`
+  Unwrap,
+  /// `x y~` rewrites to `y x`
+  Swap,
+  /// `(x) (y),` rewrites to `(x y)`
+  Concat,
+}
+
+impl Primitive {
+  pub const ALL:[Self; 6] =
+    [Self::Copy, Self::Remove, Self::Wrap, Self::Unwrap, Self::Swap, Self::Concat];
+
+  #[must_use]
+  pub const fn symbol(self) -> &'static str {
+    match self {
+      | Self::Copy => "+",
+      | Self::Remove => "-",
+      | Self::Wrap => ">",
+      | Self::Unwrap => "<",
+      | Self::Swap => "~",
+      | Self::Concat => ",",
+    }
+  }
+
+  /// The number of quotes the primitive acts on.
+  const fn arity(self) -> usize {
+    match self {
+      | Self::Swap | Self::Concat => 2,
+      | _ => 1,
+    }
+  }
+}
+
+/// The terms the primitives are written as.
+#[must_use]
+pub fn primitives(engine:&Engine) -> Vec<(Term, Primitive)> {
+  Primitive::ALL.iter()
+                .filter_map(|primitive| {
+                  parse::term(engine, primitive.symbol()).ok().map(|term| (term, *primitive))
+                })
+                .collect()
+}
+
+/// What rewrote part of a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Fired {
+  /// The loaded rule at an index
+  Rule(usize),
+  Primitive(Primitive),
+}
+
+/// A place in a program where a rule or primitive can be applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+  pub fired:Fired,
+  /// The index of the first term that is rewritten
+  pub position:usize,
+  /// The number of terms that are rewritten
+  pub len:usize,
+}
+
+/// Which of the matches in a program rewriting uses next. Among equally good
+/// matches, the first loaded rule is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+  /// The longest of the matches furthest to the left, as in
+  /// [`mlatu_lib::rewrite`]
+  Leftmost,
+  /// The longest of the matches furthest to the right
+  Rightmost,
+  /// The shortest match, furthest to the left among equally short ones
+  Shortest,
+  /// A match chosen at random from a seed. The choice depends only on the
+  /// seed and the program, so that a program that comes back is still
+  /// rewritten the same way and cycles are still found.
+  Random(u64),
+}
+
+impl Default for Strategy {
+  fn default() -> Self { Self::Leftmost }
+}
+
+impl Strategy {
+  pub const NAMES:[&'static str; 4] = ["leftmost", "rightmost", "shortest", "random"];
+
+  /// Looks up a strategy by name, using `seed` if it is random.
+  #[must_use]
+  pub fn from_name(name:&str, seed:u64) -> Option<Self> {
+    match name {
+      | "leftmost" => Some(Self::Leftmost),
+      | "rightmost" => Some(Self::Rightmost),
+      | "shortest" => Some(Self::Shortest),
+      | "random" => Some(Self::Random(seed)),
+      | _ => None,
+    }
+  }
+}
+
+impl fmt::Display for Strategy {
+  fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      | Self::Leftmost => write!(f, "leftmost"),
+      | Self::Rightmost => write!(f, "rightmost"),
+      | Self::Shortest => write!(f, "shortest"),
+      | Self::Random(seed) => write!(f, "random (seed {})", seed),
+    }
+  }
+}
+
+/// One rewrite of a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+  pub fired:Fired,
+  /// The index of the first term that was rewritten
+  pub position:usize,
+  pub before:Vector<Term>,
+  pub after:Vector<Term>,
+}
+
+/// Bounds on rewriting a program, each `None` when unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+  /// The most steps to take
+  pub steps:Option<usize>,
+  /// The largest program allowed, counting the terms inside quotes
+  pub size:Option<usize>,
+  /// The longest time to take
+  pub time:Option<Duration>,
+}
+
+impl Default for Limits {
+  fn default() -> Self { Self { steps:Some(1_000_000), size:Some(1_000_000), time:None } }
+}
+
+/// Why rewriting a program stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+  /// The program reached its normal form
+  Normal,
+  /// The step budget ran out
+  OutOfSteps,
+  /// The program grew larger than the size budget
+  TooLarge,
+  /// Rewriting took longer than the time budget
+  TimedOut,
+  /// Rewriting was cancelled, e.g. because the input changed
+  Cancelled,
+  /// The program came back to what it was after an earlier step
+  Cycle {
+    /// The step after which the program was first the repeated one
+    start:usize,
+    /// The number of steps from one repetition to the next
+    length:usize,
+  },
+}
+
+/// The number of terms in a sequence, including those inside quotes.
+#[must_use]
+pub fn size(terms:&Vector<Term>) -> usize { size_of(terms.iter()) }
+
+/// The hash of a program, which stands for it among the programs an
+/// evaluation has passed through.
+fn fingerprint(terms:&Vector<Term>) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  terms.hash(&mut hasher);
+  hasher.finish()
+}
+
+fn size_of<'a>(terms:impl Iterator<Item=&'a Term>) -> usize {
+  terms.map(|term| match term {
+         | Term::Quote(terms) => 1 + size(terms),
+         | _ => 1,
+       })
+       .sum()
+}
+
+/// Rewriting a program that is in progress or has stopped.
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+  /// The program so far, which is its normal form if the outcome is
+  /// [`Outcome::Normal`]
+  pub terms:Vector<Term>,
+  /// The number of steps taken
+  pub steps:usize,
+  /// Why rewriting stopped, or `None` if it has not
+  pub outcome:Option<Outcome>,
+  size:usize,
+  started:Instant,
+  /// The program rewriting started from
+  start:Vector<Term>,
+  /// The step after which each program so far was first reached, by the
+  /// hash of the program
+  seen:HashMap<u64, usize>,
+  /// What fired at each step
+  fired:Vec<Fired>,
+}
+
+impl Evaluation {
+  /// Starts rewriting a program that has already been resolved.
+  #[must_use]
+  pub fn new(terms:Vector<Term>) -> Self {
+    let seen = std::iter::once((fingerprint(&terms), 0)).collect();
+    Self { size:size(&terms),
+           start:terms.clone(),
+           terms,
+           steps:0,
+           outcome:None,
+           started:Instant::now(),
+           seen,
+           fired:Vec::new() }
+  }
+
+  /// What fired at each step so far.
+  #[must_use]
+  pub fn fired(&self) -> &[Fired] { &self.fired }
+
+  /// What fired at each step of the cycle the program entered, if it did.
+  #[must_use]
+  pub fn cycle(&self) -> Option<&[Fired]> {
+    match self.outcome {
+      | Some(Outcome::Cycle { start, length: _, }) => self.fired.get(start..),
+      | _ => None,
+    }
+  }
+
+  #[must_use]
+  pub const fn is_finished(&self) -> bool { self.outcome.is_some() }
+
+  /// Stops rewriting, unless it has already stopped.
+  pub fn cancel(&mut self) {
+    if self.outcome.is_none() {
+      self.outcome = Some(Outcome::Cancelled);
+    }
+  }
+}
+
+impl fmt::Display for Evaluation {
+  /// Describes why rewriting stopped, e.g. `did not terminate after 1000
+  /// steps`.
+  fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.outcome {
+      | None => write!(f, "rewriting... ({} steps so far)", self.steps),
+      | Some(Outcome::Normal) => write!(f, "normal form after {} step(s)", self.steps),
+      | Some(Outcome::OutOfSteps) => write!(f, "did not terminate after {} steps", self.steps),
+      | Some(Outcome::TooLarge) => write!(f,
+                                          "did not terminate after {} steps: the program grew to \
+                                           {} terms",
+                                          self.steps, self.size),
+      | Some(Outcome::TimedOut) => write!(f,
+                                          "did not terminate after {} steps: timed out after \
+                                           {:.1}s",
+                                          self.steps,
+                                          self.started.elapsed().as_secs_f64()),
+      | Some(Outcome::Cancelled) => write!(f, "cancelled after {} steps", self.steps),
+      | Some(Outcome::Cycle { start, length, }) => write!(f,
+                                                          "did not terminate: from step {} the \
+                                                           program repeats every {} step(s)",
+                                                          start, length),
+    }
+  }
+}
+
+/// Rewrites programs with the rules of a library one step at a time.
+pub struct Evaluator<'a> {
+  engine:&'a Engine,
+  library:&'a Library,
+  /// The indices of the rules whose pattern starts with each term, in the
+  /// order they were loaded
+  rules:HashMap<Term, Vec<usize>>,
+  primitives:Vec<(Term, Primitive)>,
+  strategy:Strategy,
+}
+
+impl<'a> Evaluator<'a> {
+  #[must_use]
+  pub fn new(engine:&'a Engine, library:&'a Library) -> Self {
+    let mut rules = HashMap::<_, Vec<_>>::new();
+    for (index, rule) in library.rules.iter().enumerate() {
+      if let Some(first) = rule.redex.front() {
+        rules.entry(first.clone()).or_default().push(index);
+      }
+    }
+    Self { engine, library, rules, primitives:primitives(engine), strategy:Strategy::default() }
+  }
+
+  /// Uses another strategy to choose the next match.
+  #[must_use]
+  pub const fn with_strategy(mut self, strategy:Strategy) -> Self {
+    self.strategy = strategy;
+    self
+  }
+
+  #[must_use]
+  pub const fn library(&self) -> &'a Library { self.library }
+
+  /// The primitive a term is, if it is one.
+  fn primitive(&self, term:&Term) -> Option<Primitive> {
+    self.primitives.iter().find(|(primitive, _)| primitive == term).map(|(_, primitive)| *primitive)
+  }
+
+  /// Every place in a program where a rule or primitive can be applied, from
+  /// left to right.
+  #[must_use]
+  pub fn matches(&self, terms:&Vector<Term>) -> Vec<Match> {
+    let mut matches = Vec::new();
+    for (position, term) in terms.iter().enumerate() {
+      for &index in self.rules.get(term).into_iter().flatten() {
+        let rule = &self.library.rules[index];
+        if position + rule.redex.len() <= terms.len()
+           && rule.redex.iter().zip(terms.iter().skip(position)).all(|(a, b)| a == b)
+        {
+          matches.push(Match { fired:Fired::Rule(index), position, len:rule.redex.len() });
+        }
+      }
+      let quotes =
+        terms.iter().skip(position).take_while(|term| matches!(term, Term::Quote(_))).count();
+      for arity in 1..=quotes.min(2) {
+        if let Some(primitive) = terms.get(position + arity)
+                                      .and_then(|term| self.primitive(term))
+                                      .filter(|primitive| primitive.arity() == arity)
+        {
+          matches.push(Match { fired:Fired::Primitive(primitive), position, len:arity + 1 });
+        }
+      }
+    }
+    matches
+  }
+
+  /// Applies a match to the program it was found in.
+  #[must_use]
+  pub fn apply(&self, terms:&Vector<Term>, found:&Match) -> Vector<Term> {
+    let mut before = terms.clone();
+    let mut rest = before.split_off(found.position);
+    let after = rest.split_off(found.len);
+    let quoted = |term:&Term| match term {
+      | Term::Quote(terms) => terms.clone(),
+      | _ => Vector::new(),
+    };
+    let replacement = match found.fired {
+      | Fired::Rule(index) => self.library.rules[index].reduction.clone(),
+      | Fired::Primitive(Primitive::Copy) => vector![rest[0].clone(), rest[0].clone()],
+      | Fired::Primitive(Primitive::Remove) => Vector::new(),
+      | Fired::Primitive(Primitive::Wrap) =>
+        vector![Term::make_quote(self.engine, vector![rest[0].clone()]).clone()],
+      | Fired::Primitive(Primitive::Unwrap) => quoted(&rest[0]),
+      | Fired::Primitive(Primitive::Swap) => vector![rest[1].clone(), rest[0].clone()],
+      | Fired::Primitive(Primitive::Concat) => {
+        let mut terms = quoted(&rest[0]);
+        terms.append(quoted(&rest[1]));
+        vector![Term::make_quote(self.engine, terms).clone()]
+      },
+    };
+    before.append(replacement);
+    before.append(after);
+    before
+  }
+
+  /// The match rewriting uses next, as chosen by the strategy.
+  #[must_use]
+  pub fn next_match(&self, terms:&Vector<Term>) -> Option<Match> {
+    // Matches are found from left to right, and in the order rules were
+    // loaded at each position, so the first of equally good ones is kept
+    let longest = |matches:Vec<Match>, position:usize| {
+      matches.into_iter()
+             .filter(|found| found.position == position)
+             .fold(None, |best:Option<Match>, found| match best {
+               | Some(best) if best.len >= found.len => Some(best),
+               | _ => Some(found),
+             })
+    };
+    let matches = self.matches(terms);
+    match self.strategy {
+      | Strategy::Leftmost => {
+        let position = matches.first()?.position;
+        longest(matches, position)
+      },
+      | Strategy::Rightmost => {
+        let position = matches.last()?.position;
+        longest(matches, position)
+      },
+      | Strategy::Shortest =>
+        matches.into_iter().fold(None, |best:Option<Match>, found| match best {
+                             | Some(best) if best.len <= found.len => Some(best),
+                             | _ => Some(found),
+                           }),
+      | Strategy::Random(seed) => {
+        if matches.is_empty() {
+          return None
+        }
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        terms.hash(&mut hasher);
+        let chosen = StdRng::seed_from_u64(hasher.finish()).gen_range(0..matches.len());
+        matches.get(chosen).copied()
+      },
+    }
+  }
+
+  /// Rewrites a program by one step, if it is not in normal form.
+  #[must_use]
+  pub fn step(&self, terms:&Vector<Term>) -> Option<Step> {
+    let found = self.next_match(terms)?;
+    Some(Step { fired:found.fired,
+                position:found.position,
+                before:terms.clone(),
+                after:self.apply(terms, &found) })
+  }
+
+  /// Every step of rewriting a program to its normal form.
+  pub fn trace(&self, terms:&Vector<Term>) -> impl Iterator<Item=Step>+'_ {
+    let mut current = terms.clone();
+    std::iter::from_fn(move || {
+      let step = self.step(&current)?;
+      current = step.after.clone();
+      Some(step)
+    })
+  }
+
+  /// Takes one step of an evaluation, unless it reaches a normal form or
+  /// exceeds a limit, in which case its outcome is set instead.
+  pub fn next_step(&self, evaluation:&mut Evaluation, limits:&Limits) -> Option<Step> {
+    if evaluation.is_finished() {
+      return None
+    }
+    let outcome = if limits.steps.map_or(false, |steps| evaluation.steps >= steps) {
+      Some(Outcome::OutOfSteps)
+    } else if limits.size.map_or(false, |size| evaluation.size > size) {
+      Some(Outcome::TooLarge)
+    } else if limits.time.map_or(false, |time| evaluation.started.elapsed() >= time) {
+      Some(Outcome::TimedOut)
+    } else {
+      None
+    };
+    if outcome.is_some() {
+      evaluation.outcome = outcome;
+      return None
+    }
+    let found = match self.next_match(&evaluation.terms) {
+      | Some(found) => found,
+      | None => {
+        evaluation.outcome = Some(Outcome::Normal);
+        return None
+      },
+    };
+    let after = self.apply(&evaluation.terms, &found);
+    // Only the rewritten terms change, so the size is updated from them
+    let added = (found.len + after.len()).saturating_sub(evaluation.terms.len());
+    let removed = size_of(evaluation.terms.iter().skip(found.position).take(found.len));
+    evaluation.size =
+      evaluation.size - removed + size_of(after.iter().skip(found.position).take(added));
+    evaluation.steps += 1;
+    evaluation.fired.push(found.fired);
+    // Only the hashes of earlier programs are kept, so a program with the
+    // same hash is taken again to tell a cycle from a collision
+    let hash = fingerprint(&after);
+    match evaluation.seen.get(&hash) {
+      | Some(&start) if self.reached(&evaluation.start, start).as_ref() == Some(&after) => {
+        evaluation.outcome = Some(Outcome::Cycle { start, length:evaluation.steps - start });
+      },
+      | Some(_) => {},
+      | None => {
+        let _previous = evaluation.seen.insert(hash, evaluation.steps);
+      },
+    }
+    let before = std::mem::replace(&mut evaluation.terms, after.clone());
+    Some(Step { fired:found.fired, position:found.position, before, after })
+  }
+
+  /// The program rewriting a program reaches after some steps, if it takes
+  /// that many.
+  fn reached(&self, terms:&Vector<Term>, steps:usize) -> Option<Vector<Term>> {
+    match steps.checked_sub(1) {
+      | Some(last) => self.trace(terms).nth(last).map(|step| step.after),
+      | None => Some(terms.clone()),
+    }
+  }
+
+  /// Advances an evaluation by at most `slice` steps.
+  pub fn advance(&self, evaluation:&mut Evaluation, limits:&Limits, slice:usize) {
+    for _ in 0..slice {
+      if self.next_step(evaluation, limits).is_none() {
+        return
+      }
+    }
+  }
+
+  /// Rewrites a program until it reaches a normal form or exceeds a limit.
+  #[must_use]
+  pub fn evaluate(&self, terms:&Vector<Term>, limits:&Limits) -> Evaluation {
+    let mut evaluation = self.start(terms);
+    while self.next_step(&mut evaluation, limits).is_some() {}
+    evaluation
+  }
+
+  /// Starts rewriting a program. Programs written by users should be passed
+  /// through [`Library::resolve`] first.
+  #[must_use]
+  pub fn start(&self, terms:&Vector<Term>) -> Evaluation { Evaluation::new(terms.clone()) }
+
+  /// A description of the rules and primitives a cycle goes through, each
+  /// once in the order they first fire, if the evaluation entered one.
+  #[must_use]
+  pub fn describe_cycle(&self, evaluation:&Evaluation) -> Option<String> {
+    let mut involved = Vec::new();
+    for fired in evaluation.cycle()? {
+      if !involved.contains(fired) {
+        involved.push(*fired);
+      }
+    }
+    Some(involved.into_iter().map(|fired| self.describe(fired)).collect::<Vec<_>>().join(", "))
+  }
+
+  /// A description of what fired, e.g. `rule 3 of nat.mlt:12` or
+  /// ``primitive `+` ``.
+  #[must_use]
+  pub fn describe(&self, fired:Fired) -> String {
+    match fired {
+      | Fired::Rule(index) =>
+        self.library
+            .origin(index)
+            .map_or_else(|| format!("rule {}", index + 1), ToString::to_string),
+      | Fired::Primitive(primitive) => format!("primitive `{}`", primitive.symbol()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use mlatu_lib::{parse, rewrite, Engine};
+
+  use super::{Evaluator, Limits, Outcome, Strategy};
+  use crate::library::tests::parsed;
+  use crate::pretty_terms;
+
+  #[test]
+  fn leftmost_agrees_with_the_library() {
+    let engine = Engine::new();
+    let library =
+      parsed(&engine, "a = x. b = y. a b = ab. x y = xy. ab = (a) (b). swap = ~. both = ,.");
+    let evaluator = Evaluator::new(&engine, &library);
+    for program in ["a b",
+                    "b a",
+                    "x a b y",
+                    "a b swap",
+                    "a b both <",
+                    "(a b) + - <",
+                    "((a) >) < <",
+                    "(b) (a) ~ , <"]
+    {
+      let terms = parse::terms(&engine, program).expect("the program parses");
+      let evaluation = evaluator.evaluate(&terms, &Limits::default());
+      assert_eq!(evaluation.outcome, Some(Outcome::Normal), "{}", program);
+      assert_eq!(evaluation.terms, rewrite(&engine, &library.rules, terms), "{}", program);
+    }
+  }
+
+  #[test]
+  fn stops_when_a_program_comes_back() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = b. b = c. c = b.");
+    let terms = parse::terms(&engine, "a").expect("the program parses");
+    let evaluation = Evaluator::new(&engine, &library).evaluate(&terms, &Limits::default());
+    assert_eq!(evaluation.outcome, Some(Outcome::Cycle { start:1, length:2 }));
+    assert_eq!(evaluation.steps, 3);
+  }
+
+  #[test]
+  fn each_strategy_chooses_its_match() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = x. b c = y. c = z.");
+    let terms = parse::terms(&engine, "b c a").expect("the program parses");
+    let first_step = |strategy:Strategy| {
+      Evaluator::new(&engine, &library).with_strategy(strategy)
+                                       .step(&terms)
+                                       .map(|step| pretty_terms(&engine, step.after))
+    };
+    assert_eq!(first_step(Strategy::Leftmost).as_deref(), Some("y a"));
+    assert_eq!(first_step(Strategy::Rightmost).as_deref(), Some("b c x"));
+    assert_eq!(first_step(Strategy::Shortest).as_deref(), Some("b z a"));
+    let random = Evaluator::new(&engine, &library).with_strategy(Strategy::Random(5));
+    let chosen = random.next_match(&terms).expect("a match is chosen");
+    assert!(random.matches(&terms).contains(&chosen));
+    assert_eq!(random.next_match(&terms), Some(chosen));
+    assert_eq!(Strategy::from_name("random", 5), Some(Strategy::Random(5)));
+    assert_eq!(Strategy::from_name("outermost", 5), None);
+  }
+
+  #[test]
+  fn prefers_the_longest_match_then_the_first_rule() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = x. a b = first. a b = second. b = y.");
+    let terms = parse::terms(&engine, "b a b").expect("the program parses");
+    let steps = Evaluator::new(&engine, &library).with_strategy(Strategy::Leftmost)
+                                                 .trace(&terms)
+                                                 .map(|step| step.after)
+                                                 .collect::<Vec<_>>();
+    let expected =
+      ["y a b", "y first"].iter()
+                          .map(|program| {
+                            parse::terms(&engine, program).expect("the program parses")
+                          })
+                          .collect::<Vec<_>>();
+    assert_eq!(steps, expected);
+  }
+}
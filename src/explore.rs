@@ -0,0 +1,253 @@
+//! Finding every program a program can be rewritten to, for `mlatu explore`.
+//!
+//! Rewriting normally takes one match at each step, but a program can have
+//! several, and they need not lead to the same normal form. Starting from a
+//! program, every match is applied in turn, breadth-first, to build the graph
+//! of the distinct programs reached and the steps between them, up to a
+//! depth, a number of programs and a size.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+
+use im::Vector;
+use mlatu_lib::{Engine, Term};
+use serde_json::json;
+
+use crate::eval::{size, Evaluator, Fired};
+use crate::pretty_terms;
+use crate::trace::fired_json;
+
+/// How a reduction graph is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  /// The normal forms reached, one per line
+  Text,
+  /// The whole graph in the Graphviz DOT language, with normal forms drawn
+  /// as double circles
+  Dot,
+  /// The whole graph as a JSON object with its nodes and edges
+  Json,
+}
+
+impl Format {
+  /// Looks up a graph format by name.
+  #[must_use]
+  pub fn from_name(name:&str) -> Option<Self> {
+    match name {
+      | "text" => Some(Self::Text),
+      | "dot" => Some(Self::Dot),
+      | "json" => Some(Self::Json),
+      | _ => None,
+    }
+  }
+}
+
+/// How far exploring goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+  /// The most steps from the starting program
+  pub depth:usize,
+  /// The most distinct programs
+  pub programs:usize,
+  /// The largest program that is rewritten further, counting the terms
+  /// inside quotes
+  pub size:Option<usize>,
+}
+
+impl Default for Bounds {
+  fn default() -> Self { Self { depth:20, programs:10_000, size:Some(10_000) } }
+}
+
+/// A program reached while exploring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+  pub terms:Vector<Term>,
+  /// The fewest steps it takes to reach the program
+  pub depth:usize,
+  /// Whether no rule or primitive applies to the program
+  pub normal:bool,
+  /// Whether the program was not rewritten further because of the bounds
+  pub cut_off:bool,
+}
+
+/// One step from a program to another, by their indices in the graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+  pub from:usize,
+  pub to:usize,
+  pub fired:Fired,
+  /// The index of the first term that was rewritten
+  pub position:usize,
+}
+
+/// The programs reachable from a program, and the steps between them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Graph {
+  /// Every program reached, the starting one first, in the order they were
+  /// reached
+  pub nodes:Vec<Node>,
+  pub edges:Vec<Edge>,
+}
+
+impl Graph {
+  /// The normal forms reached, in the order they were reached.
+  pub fn normal_forms(&self) -> impl Iterator<Item=&Node> {
+    self.nodes.iter().filter(|node| node.normal)
+  }
+
+  /// Whether every program reachable from the starting one was explored.
+  #[must_use]
+  pub fn is_complete(&self) -> bool { self.nodes.iter().all(|node| !node.cut_off) }
+}
+
+/// Explores every way of rewriting a resolved program within some bounds.
+#[must_use]
+pub fn explore(evaluator:&Evaluator<'_>, terms:&Vector<Term>, bounds:&Bounds) -> Graph {
+  let start = terms.clone();
+  let mut graph = Graph { nodes:Vec::new(), edges:Vec::new() };
+  let mut indices = HashMap::new();
+  let mut queue = VecDeque::new();
+  let _previous = indices.insert(start.clone(), 0);
+  graph.nodes.push(Node { terms:start, depth:0, normal:false, cut_off:false });
+  queue.push_back(0);
+  while let Some(from) = queue.pop_front() {
+    let node = &graph.nodes[from];
+    let matches = evaluator.matches(&node.terms);
+    if matches.is_empty() {
+      graph.nodes[from].normal = true;
+      continue
+    }
+    if node.depth >= bounds.depth || bounds.size.map_or(false, |limit| size(&node.terms) > limit) {
+      graph.nodes[from].cut_off = true;
+      continue
+    }
+    let (terms, depth) = (node.terms.clone(), node.depth + 1);
+    for found in matches {
+      let after = evaluator.apply(&terms, &found);
+      let to = match indices.get(&after) {
+        | Some(&to) => to,
+        | None if graph.nodes.len() < bounds.programs => {
+          let to = graph.nodes.len();
+          let _previous = indices.insert(after.clone(), to);
+          graph.nodes.push(Node { terms:after, depth, normal:false, cut_off:false });
+          queue.push_back(to);
+          to
+        },
+        | None => {
+          graph.nodes[from].cut_off = true;
+          continue
+        },
+      };
+      graph.edges.push(Edge { from, to, fired:found.fired, position:found.position });
+    }
+  }
+  graph
+}
+
+/// Escapes a string for a double-quoted DOT identifier.
+fn dot_string(text:&str) -> String { text.replace('\\', "\\\\").replace('"', "\\\"") }
+
+/// Prints a reduction graph.
+///
+/// # Errors
+///
+/// Returns `Err` if the graph could not be written
+pub fn write(engine:&Engine, evaluator:&Evaluator<'_>, graph:&Graph, format:Format,
+             out:&mut impl Write)
+             -> io::Result<()> {
+  let program = |node:&Node| pretty_terms(engine, node.terms.clone());
+  match format {
+    | Format::Text =>
+      for node in graph.normal_forms() {
+        writeln!(out, "{}", program(node))?;
+      },
+    | Format::Dot => {
+      writeln!(out, "digraph reductions {{")?;
+      for (index, node) in graph.nodes.iter().enumerate() {
+        let shape = match (node.normal, node.cut_off) {
+          | (true, _) => ", shape=doublecircle",
+          | (_, true) => ", style=dashed",
+          | _ => "",
+        };
+        writeln!(out, "  n{} [label=\"{}\"{}];", index, dot_string(&program(node)), shape)?;
+      }
+      for edge in &graph.edges {
+        writeln!(out,
+                 "  n{} -> n{} [label=\"{} at {}\"];",
+                 edge.from,
+                 edge.to,
+                 dot_string(&evaluator.describe(edge.fired)),
+                 edge.position)?;
+      }
+      writeln!(out, "}}")?;
+    },
+    | Format::Json => {
+      let nodes = graph.nodes
+                       .iter()
+                       .enumerate()
+                       .map(|(index, node)| {
+                         json!({ "id": index,
+                                 "program": program(node),
+                                 "depth": node.depth,
+                                 "normal": node.normal,
+                                 "cut_off": node.cut_off })
+                       })
+                       .collect::<Vec<_>>();
+      let edges = graph.edges
+                       .iter()
+                       .map(|edge| {
+                         let mut value = fired_json(evaluator, edge.fired);
+                         value["from"] = json!(edge.from);
+                         value["to"] = json!(edge.to);
+                         value["position"] = json!(edge.position);
+                         value
+                       })
+                       .collect::<Vec<_>>();
+      let normal_forms = graph.normal_forms().map(program).collect::<Vec<_>>();
+      writeln!(out,
+               "{}",
+               json!({ "nodes": nodes,
+                       "edges": edges,
+                       "normal_forms": normal_forms,
+                       "complete": graph.is_complete() }))?;
+    },
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use mlatu_lib::{parse, Engine};
+
+  use super::{explore, Bounds};
+  use crate::eval::Evaluator;
+  use crate::library::tests::parsed;
+  use crate::pretty_terms;
+
+  #[test]
+  fn reaches_every_normal_form() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = x. a = y. b = z.");
+    let evaluator = Evaluator::new(&engine, &library);
+    let terms = parse::terms(&engine, "a b").expect("the program parses");
+    let graph = explore(&evaluator, &terms, &Bounds::default());
+    let normal_forms = graph.normal_forms()
+                            .map(|node| pretty_terms(&engine, node.terms.clone()))
+                            .collect::<Vec<_>>();
+    assert_eq!(normal_forms, vec!["x z", "y z"]);
+    assert_eq!((graph.nodes.len(), graph.edges.len()), (6, 7));
+    assert!(graph.is_complete());
+  }
+
+  #[test]
+  fn stops_at_the_depth() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = x. a = y. b = z.");
+    let evaluator = Evaluator::new(&engine, &library);
+    let terms = parse::terms(&engine, "a b").expect("the program parses");
+    let graph = explore(&evaluator, &terms, &Bounds { depth:1, ..Bounds::default() });
+    assert_eq!(graph.nodes.len(), 4);
+    assert_eq!(graph.normal_forms().count(), 0);
+    assert!(!graph.is_complete());
+  }
+}
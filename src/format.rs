@@ -0,0 +1,141 @@
+//! The on-disk formats rule files can be stored in.
+
+pub mod binary;
+pub mod json;
+pub mod text;
+
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+use im::Vector;
+use mlatu_lib::{Engine, Rule};
+
+/// A directive to load the rules of another file before those of the file
+/// containing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Include {
+  /// The included path, as written in the file
+  pub path:String,
+  /// The byte range of the directive in text files
+  pub span:Option<Range<usize>>,
+}
+
+/// Everything stored in a rule file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Contents {
+  pub includes:Vec<Include>,
+  /// The atoms other files can use, in their pretty-printed form, if the file
+  /// has an export list. Without one, every atom is exported.
+  pub exports:Option<Vec<String>>,
+  pub rules:Vector<Rule>,
+  /// The byte ranges of the rules in text files, in the same order
+  pub rule_spans:Vec<Range<usize>>,
+}
+
+/// The ways reading a rule file can fail once its bytes have been read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+  /// The bytes could not be decoded (invalid UTF-8, or malformed binary or
+  /// JSON data), with the byte offset of the problem in text formats if it is
+  /// known
+  Decode { message:String, offset:Option<usize>, },
+  /// The decoded text could not be parsed into rules
+  Parse(Vec<text::SyntaxError>),
+}
+
+impl Error {
+  pub(crate) fn decode(message:impl Into<String>) -> Self {
+    Self::Decode { message:message.into(), offset:None }
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      | Self::Decode { message, offset: _, } => write!(f, "{}", message),
+      | Self::Parse(errors) =>
+        write!(f,
+               "{}",
+               errors.iter().map(|error| error.message.as_str()).collect::<Vec<_>>().join("; ")),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  /// mlatu text files (`.mlt`), as parsed by `mlatu_lib::parse::rules`
+  Text,
+  /// mlatu binary files (`.mlb`), see [`binary`]
+  Binary,
+  /// JSON files (`.json`), see [`json`]
+  Json,
+}
+
+impl Format {
+  /// All supported formats.
+  pub const ALL:[Self; 3] = [Self::Text, Self::Binary, Self::Json];
+
+  /// The format a file should be written in, based on its extension.
+  #[must_use]
+  pub fn from_path(path:&Path) -> Option<Self> { Self::from_name(path.extension()?.to_str()?) }
+
+  /// Looks up a format by its name or its file extension.
+  #[must_use]
+  pub fn from_name(name:&str) -> Option<Self> {
+    match name {
+      | "mlt" | "text" => Some(Self::Text),
+      | "mlb" | "binary" => Some(Self::Binary),
+      | "json" => Some(Self::Json),
+      | _ => None,
+    }
+  }
+
+  /// The file extension of this format.
+  #[must_use]
+  pub const fn extension(self) -> &'static str {
+    match self {
+      | Self::Text => "mlt",
+      | Self::Binary => "mlb",
+      | Self::Json => "json",
+    }
+  }
+
+  /// The format of the contents of a file. Binary files are recognized by
+  /// their magic bytes, so `.mlb` files written as text by older versions
+  /// still load, and JSON files by their extension.
+  #[must_use]
+  pub fn detect(path:&Path, bytes:&[u8]) -> Self {
+    if bytes.starts_with(binary::MAGIC) {
+      Self::Binary
+    } else if Self::from_path(path) == Some(Self::Json) {
+      Self::Json
+    } else {
+      Self::Text
+    }
+  }
+
+  /// # Errors
+  ///
+  /// Returns `Err` if the bytes could not be decoded or parsed in this format
+  pub fn read(self, engine:&Engine, bytes:Vec<u8>) -> Result<Contents, Error> {
+    match self {
+      | Self::Text => {
+        let string = String::from_utf8(bytes).map_err(|e| Error::Decode { message:e.to_string(),
+                                                  offset:Some(e.utf8_error().valid_up_to()) })?;
+        text::parse(engine, &string).map_err(Error::Parse)
+      },
+      | Self::Binary => binary::decode(engine, &bytes).map_err(Error::decode),
+      | Self::Json => json::decode(engine, &bytes),
+    }
+  }
+
+  #[must_use]
+  pub fn write(self, engine:&Engine, contents:Contents) -> Vec<u8> {
+    match self {
+      | Self::Text => text::write(engine, contents).into_bytes(),
+      | Self::Binary => binary::encode(engine, &contents),
+      | Self::Json => json::encode(engine, contents),
+    }
+  }
+}
@@ -0,0 +1,309 @@
+//! The binary rule format, used for `.mlb` files.
+//!
+//! A file starts with the [`MAGIC`] bytes and a one-byte format version,
+//! followed by the atom table, the include table and the rules. All numbers
+//! are unsigned LEB128 varints. The atom table is a count followed by that
+//! many length-prefixed UTF-8 atom names; atoms are stored in their
+//! pretty-printed form. The include table is a count followed by that many
+//! length-prefixed UTF-8 paths. The export list is `0` if the file has none,
+//! or otherwise one more than the number of length-prefixed UTF-8 atom names
+//! that follow. The rules
+//! are a count followed by the redex and reduction of each rule, each of
+//! which is a sequence: a length followed by that many terms. A term is a
+//! single varint `n`; if `n` is even it is the atom at index `n / 2` in the
+//! atom table, and if it is odd it is a quote containing the sequence of
+//! `n / 2` terms that follows.
+
+use std::collections::HashMap;
+
+use im::Vector;
+use mlatu_lib::{parse, pretty, Engine, Rule, Term};
+
+use super::{Contents, Include};
+
+/// The bytes every binary rule file starts with.
+pub const MAGIC:&[u8; 4] = b"\x7fMLB";
+
+/// The version of the binary format written by [`encode`].
+pub const VERSION:u8 = 1;
+
+/// The deepest quotes can be nested in decoded data, so that crafted files
+/// are reported as malformed rather than overflowing the stack.
+pub const MAX_DEPTH:usize = 1000;
+
+struct Encoder<'a> {
+  engine:&'a Engine,
+  atoms:Vec<String>,
+  indices:HashMap<String, usize>,
+  body:Vec<u8>,
+}
+
+impl Encoder<'_> {
+  fn varint(bytes:&mut Vec<u8>, mut n:usize) {
+    loop {
+      let byte = u8::try_from(n & 0x7f).expect("masked to 7 bits");
+      n >>= 7;
+      if n == 0 {
+        bytes.push(byte);
+        break
+      }
+      bytes.push(byte | 0x80);
+    }
+  }
+
+  fn atom(&mut self, term:&Term) -> usize {
+    let name = pretty::term(self.engine, term.clone());
+    if let Some(index) = self.indices.get(&name) {
+      *index
+    } else {
+      let index = self.atoms.len();
+      let _previous = self.indices.insert(name.clone(), index);
+      self.atoms.push(name);
+      index
+    }
+  }
+
+  fn terms(&mut self, terms:&Vector<Term>) {
+    Self::varint(&mut self.body, terms.len());
+    for term in terms {
+      self.term(term);
+    }
+  }
+
+  fn term(&mut self, term:&Term) {
+    match term {
+      | Term::Quote(terms) => {
+        Self::varint(&mut self.body, terms.len() << 1 | 1);
+        for term in terms {
+          self.term(term);
+        }
+      },
+      | _ => {
+        let index = self.atom(term);
+        Self::varint(&mut self.body, index << 1);
+      },
+    }
+  }
+}
+
+fn string(bytes:&mut Vec<u8>, string:&str) {
+  Encoder::varint(bytes, string.len());
+  bytes.extend_from_slice(string.as_bytes());
+}
+
+/// Encodes include directives and rules in the binary rule format.
+#[must_use]
+pub fn encode(engine:&Engine, contents:&Contents) -> Vec<u8> {
+  let mut encoder = Encoder { engine, atoms:Vec::new(), indices:HashMap::new(), body:Vec::new() };
+  Encoder::varint(&mut encoder.body, contents.rules.len());
+  for rule in &contents.rules {
+    encoder.terms(&rule.redex);
+    encoder.terms(&rule.reduction);
+  }
+  let mut bytes = MAGIC.to_vec();
+  bytes.push(VERSION);
+  Encoder::varint(&mut bytes, encoder.atoms.len());
+  for atom in &encoder.atoms {
+    string(&mut bytes, atom);
+  }
+  Encoder::varint(&mut bytes, contents.includes.len());
+  for include in &contents.includes {
+    string(&mut bytes, &include.path);
+  }
+  match &contents.exports {
+    | Some(exports) => {
+      Encoder::varint(&mut bytes, exports.len() + 1);
+      for export in exports {
+        string(&mut bytes, export);
+      }
+    },
+    | None => Encoder::varint(&mut bytes, 0),
+  }
+  bytes.extend(encoder.body);
+  bytes
+}
+
+struct Decoder<'a> {
+  bytes:&'a [u8],
+  position:usize,
+  atoms:Vec<Term>,
+}
+
+impl Decoder<'_> {
+  fn byte(&mut self) -> Result<u8, String> {
+    let byte =
+      self.bytes.get(self.position).copied().ok_or_else(|| "unexpected end of data".to_string())?;
+    self.position += 1;
+    Ok(byte)
+  }
+
+  fn varint(&mut self) -> Result<usize, String> {
+    let mut n = 0_usize;
+    let mut shift = 0_u32;
+    loop {
+      let byte = self.byte()?;
+      let bits = usize::from(byte & 0x7f);
+      if shift >= usize::BITS || (bits << shift) >> shift != bits {
+        return Err(format!("number too large at byte {}", self.position - 1))
+      }
+      n |= bits << shift;
+      if byte & 0x80 == 0 {
+        return Ok(n)
+      }
+      shift += 7;
+    }
+  }
+
+  fn string(&mut self, table:&str) -> Result<&str, String> {
+    let len = self.varint()?;
+    let start = self.position;
+    let end = start.checked_add(len).filter(|end| *end <= self.bytes.len()).ok_or_else(|| {
+                                                                              format!("unexpected \
+                                                                                       end of data \
+                                                                                       in {} table",
+                                                                                      table)
+                                                                            })?;
+    self.position = end;
+    std::str::from_utf8(&self.bytes[start..end]).map_err(|e| {
+                                                  format!("invalid {} at byte {}: {}",
+                                                          table, start, e)
+                                                })
+  }
+
+  fn include_table(&mut self) -> Result<Vec<Include>, String> {
+    let count = self.varint()?;
+    let mut includes = Vec::new();
+    for _ in 0..count {
+      includes.push(Include { path:self.string("include")?.to_string(), span:None });
+    }
+    Ok(includes)
+  }
+
+  fn export_list(&mut self) -> Result<Option<Vec<String>>, String> {
+    match self.varint()? {
+      | 0 => Ok(None),
+      | count => (1..count).map(|_| self.string("export").map(ToString::to_string))
+                           .collect::<Result<_, _>>()
+                           .map(Some),
+    }
+  }
+
+  fn atom_table(&mut self, engine:&Engine) -> Result<(), String> {
+    let count = self.varint()?;
+    for _ in 0..count {
+      let name = self.string("atom")?;
+      let term =
+        parse::term(engine, name).map_err(|e| format!("invalid atom name '{}': {}", name, e))?;
+      self.atoms.push(term);
+    }
+    Ok(())
+  }
+
+  fn terms(&mut self, engine:&Engine) -> Result<Vector<Term>, String> {
+    let len = self.varint()?;
+    self.sequence(engine, len, 0)
+  }
+
+  fn sequence(&mut self, engine:&Engine, len:usize, depth:usize) -> Result<Vector<Term>, String> {
+    let mut terms = Vector::new();
+    for _ in 0..len {
+      terms.push_back(self.term(engine, depth)?);
+    }
+    Ok(terms)
+  }
+
+  fn term(&mut self, engine:&Engine, depth:usize) -> Result<Term, String> {
+    let start = self.position;
+    let n = self.varint()?;
+    if n & 1 == 0 {
+      self.atoms.get(n >> 1).cloned().ok_or_else(|| {
+                                       format!("atom index {} out of range at byte {}",
+                                               n >> 1,
+                                               start)
+                                     })
+    } else if depth >= MAX_DEPTH {
+      Err(format!("quotes nested more than {} deep at byte {}", MAX_DEPTH, start))
+    } else {
+      let terms = self.sequence(engine, n >> 1, depth + 1)?;
+      Ok(Term::make_quote(engine, terms).clone())
+    }
+  }
+}
+
+/// Decodes include directives and rules in the binary rule format.
+///
+/// # Errors
+///
+/// Returns `Err` if the data does not start with the magic bytes, has an
+/// unsupported version, or is otherwise malformed
+pub fn decode(engine:&Engine, bytes:&[u8]) -> Result<Contents, String> {
+  if !bytes.starts_with(MAGIC) {
+    return Err("missing magic bytes".to_string())
+  }
+  let mut decoder = Decoder { bytes, position:MAGIC.len(), atoms:Vec::new() };
+  let version = decoder.byte()?;
+  if version != VERSION {
+    return Err(format!("unsupported format version {} (expected {})", version, VERSION))
+  }
+  decoder.atom_table(engine)?;
+  let includes = decoder.include_table()?;
+  let exports = decoder.export_list()?;
+  let count = decoder.varint()?;
+  let mut rules = Vector::new();
+  for _ in 0..count {
+    let redex = decoder.terms(engine)?;
+    let reduction = decoder.terms(engine)?;
+    rules.push_back(Rule { redex, reduction });
+  }
+  if decoder.position == bytes.len() {
+    Ok(Contents { includes, exports, rules, rule_spans:Vec::new() })
+  } else {
+    Err(format!("unexpected trailing data at byte {}", decoder.position))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use mlatu_lib::{parse, Engine};
+
+  use super::{decode, encode, MAX_DEPTH};
+  use crate::format::{Contents, Include};
+
+  fn contents(engine:&Engine) -> Contents {
+    Contents { includes:vec![Include { path:"nat.mlt".to_string(), span:None }],
+               exports:Some(vec!["swap".to_string(), "dup".to_string()]),
+               rules:parse::rules(engine, "swap = ~. dup = +. a (b (c) ()) = (a) b b.")
+                 .expect("rules parse"),
+               rule_spans:Vec::new() }
+  }
+
+  #[test]
+  fn round_trips() {
+    let engine = Engine::new();
+    let contents = contents(&engine);
+    assert_eq!(decode(&engine, &encode(&engine, &contents)), Ok(contents));
+    let empty = Contents::default();
+    assert_eq!(decode(&engine, &encode(&engine, &empty)), Ok(empty));
+  }
+
+  #[test]
+  fn rejects_truncated_data() {
+    let engine = Engine::new();
+    let bytes = encode(&engine, &contents(&engine));
+    for len in 0..bytes.len() {
+      assert!(decode(&engine, &bytes[..len]).is_err(), "decoded {} of {} bytes", len, bytes.len());
+    }
+  }
+
+  #[test]
+  fn rejects_deeply_nested_quotes() {
+    let engine = Engine::new();
+    let mut bytes = encode(&engine, &Contents::default());
+    // One rule whose redex is a single quote, nested far too deep
+    let _count = bytes.pop();
+    bytes.extend([1, 1]);
+    bytes.extend(std::iter::repeat(3).take(MAX_DEPTH * 100));
+    bytes.extend([1, 0]);
+    assert!(decode(&engine, &bytes).expect_err("quotes are too deep").contains("nested"));
+  }
+}
@@ -0,0 +1,182 @@
+//! The JSON rule format, for exchanging rules with other tools.
+//!
+//! A file is an object with a `version` (currently 1), an optional
+//! `includes` array of paths, an optional `exports` array of atoms and a
+//! `rules` array. Each rule is an object with `redex` and `reduction` arrays
+//! of terms; an atom is a string containing its pretty-printed form and a
+//! quote is an array of terms.
+
+use im::Vector;
+use mlatu_lib::{parse, pretty, Engine, Rule, Term};
+use serde_json::{json, Value};
+
+use super::{Contents, Error, Include};
+
+/// The version of the JSON format written by [`encode`].
+pub const VERSION:u64 = 1;
+
+fn encode_terms(engine:&Engine, terms:Vector<Term>) -> Value {
+  Value::Array(terms.into_iter().map(|term| encode_term(engine, term)).collect())
+}
+
+fn encode_term(engine:&Engine, term:Term) -> Value {
+  match term {
+    | Term::Quote(terms) => encode_terms(engine, terms),
+    | term => Value::String(pretty::term(engine, term)),
+  }
+}
+
+/// Encodes include directives and rules in the JSON rule format.
+#[must_use]
+pub fn encode(engine:&Engine, contents:Contents) -> Vec<u8> {
+  let rules = contents.rules
+                      .into_iter()
+                      .map(|rule| {
+                        json!({ "redex": encode_terms(engine, rule.redex),
+                             "reduction": encode_terms(engine, rule.reduction) })
+                      })
+                      .collect::<Vec<_>>();
+  let mut value = json!({ "version": VERSION, "rules": rules });
+  if !contents.includes.is_empty() {
+    value["includes"] =
+      contents.includes.into_iter().map(|include| Value::String(include.path)).collect();
+  }
+  if let Some(exports) = contents.exports {
+    value["exports"] = exports.into_iter().map(Value::String).collect();
+  }
+  format!("{:#}\n", value).into_bytes()
+}
+
+fn decode_terms(engine:&Engine, value:&Value, at:&str) -> Result<Vector<Term>, String> {
+  let values = value.as_array().ok_or_else(|| format!("expected an array of terms at {}", at))?;
+  values.iter()
+        .enumerate()
+        .map(|(i, value)| decode_term(engine, value, &format!("{}[{}]", at, i)))
+        .collect()
+}
+
+fn decode_term(engine:&Engine, value:&Value, at:&str) -> Result<Term, String> {
+  match value {
+    | Value::String(name) =>
+      parse::term(engine, name).map_err(|e| format!("invalid atom '{}' at {}: {}", name, at, e)),
+    | Value::Array(_) => Ok(Term::make_quote(engine, decode_terms(engine, value, at)?).clone()),
+    | _ => Err(format!("expected an atom or a quote at {}", at)),
+  }
+}
+
+/// The byte offset of a line and column as reported by `serde_json`.
+fn offset(bytes:&[u8], line:usize, column:usize) -> usize {
+  let line_start = bytes.split(|byte| *byte == b'\n')
+                        .take(line.saturating_sub(1))
+                        .map(|line| line.len() + 1)
+                        .sum::<usize>();
+  (line_start + column.saturating_sub(1)).min(bytes.len())
+}
+
+/// Decodes include directives and rules in the JSON rule format.
+///
+/// # Errors
+///
+/// Returns `Err` if the data is not valid JSON, has an unsupported version,
+/// or does not have the expected structure
+pub fn decode(engine:&Engine, bytes:&[u8]) -> Result<Contents, Error> {
+  let value:Value =
+    serde_json::from_slice(bytes).map_err(|e| Error::Decode { message:e.to_string(),
+                                                              offset:Some(offset(bytes,
+                                                                                 e.line(),
+                                                                                 e.column())) })?;
+  decode_contents(engine, &value).map_err(Error::decode)
+}
+
+fn decode_includes(value:&Value) -> Result<Vec<Include>, String> {
+  let includes = match value.get("includes") {
+    | Some(includes) =>
+      includes.as_array().ok_or_else(|| "expected an includes array".to_string())?,
+    | None => return Ok(Vec::new()),
+  };
+  includes.iter()
+          .enumerate()
+          .map(|(i, path)| {
+            path.as_str()
+                .map(|path| Include { path:path.to_string(), span:None })
+                .ok_or_else(|| format!("expected a path at includes[{}]", i))
+          })
+          .collect()
+}
+
+fn decode_exports(engine:&Engine, value:&Value) -> Result<Option<Vec<String>>, String> {
+  let exports = match value.get("exports") {
+    | Some(exports) => exports.as_array().ok_or_else(|| "expected an exports array".to_string())?,
+    | None => return Ok(None),
+  };
+  exports.iter()
+         .enumerate()
+         .map(|(i, name)| {
+           let at = format!("exports[{}]", i);
+           let name = name.as_str().ok_or_else(|| format!("expected an atom at {}", at))?;
+           parse::term(engine, name).map(|term| pretty::term(engine, term))
+                                    .map_err(|e| format!("invalid atom '{}' at {}: {}", name, at, e))
+         })
+         .collect::<Result<_, _>>()
+         .map(Some)
+}
+
+fn decode_contents(engine:&Engine, value:&Value) -> Result<Contents, String> {
+  match value.get("version").and_then(Value::as_u64) {
+    | Some(VERSION) => {},
+    | Some(version) =>
+      return Err(format!("unsupported format version {} (expected {})", version, VERSION)),
+    | None => return Err("missing format version".to_string()),
+  }
+  let rules = value.get("rules")
+                   .and_then(Value::as_array)
+                   .ok_or_else(|| "expected a rules array".to_string())?;
+  let rules =
+    rules.iter()
+         .enumerate()
+         .map(|(i, rule)| {
+           let at = format!("rules[{}]", i);
+           let side = |name:&str| {
+             rule.get(name)
+                 .ok_or_else(|| format!("missing {} at {}", name, at))
+                 .and_then(|value| decode_terms(engine, value, &format!("{}.{}", at, name)))
+           };
+           Ok(Rule { redex:side("redex")?, reduction:side("reduction")? })
+         })
+         .collect::<Result<_, String>>()?;
+  Ok(Contents { includes:decode_includes(value)?,
+                exports:decode_exports(engine, value)?,
+                rules,
+                rule_spans:Vec::new() })
+}
+
+#[cfg(test)]
+mod tests {
+  use mlatu_lib::{parse, Engine};
+
+  use super::{decode, encode};
+  use crate::format::{Contents, Error, Include};
+
+  #[test]
+  fn round_trips() {
+    let engine = Engine::new();
+    let contents =
+      Contents { includes:vec![Include { path:"nat.mlt".to_string(), span:None }],
+                 exports:Some(vec!["swap".to_string()]),
+                 rules:parse::rules(&engine, "swap = ~. a (b (c) ()) = (a) b b. x = .")
+                   .expect("rules parse"),
+                 rule_spans:Vec::new() };
+    assert_eq!(decode(&engine, &encode(&engine, contents.clone())), Ok(contents));
+    let empty = Contents::default();
+    assert_eq!(decode(&engine, &encode(&engine, empty.clone())), Ok(empty));
+  }
+
+  #[test]
+  fn reports_the_offset_of_syntax_errors() {
+    let engine = Engine::new();
+    match decode(&engine, b"{\n  \"version\": 1,\n  \"rules\": [,]\n}") {
+      | Err(Error::Decode { message: _, offset, }) => assert_eq!(offset, Some(30)),
+      | other => panic!("expected a decoding error, got {:?}", other),
+    }
+  }
+}
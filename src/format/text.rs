@@ -0,0 +1,269 @@
+//! The text rule format, used for `.mlt` files.
+//!
+//! Rules are parsed by `mlatu_lib::parse::rules`, but the source is first
+//! split into rules (each ending with a `.`) so that every bad rule can be
+//! reported with its location, rather than only the first one. Besides
+//! rules, a file can contain include directives such as `include
+//! "nat.mlt".` and export lists such as `export zero succ add.`
+
+use std::ops::Range;
+
+use mlatu_lib::{parse, pretty, Engine};
+
+use super::{Contents, Include};
+
+/// An error in a text rule file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+  /// The byte range of the bad token or rule
+  pub span:Range<usize>,
+  pub message:String,
+}
+
+impl SyntaxError {
+  fn new(span:Range<usize>, message:impl Into<String>) -> Self {
+    Self { span, message:message.into() }
+  }
+}
+
+/// The path of an include directive, if the text is one.
+pub(crate) fn include(text:&str) -> Option<&str> {
+  let rest = text.strip_prefix("include")?;
+  if !rest.starts_with(char::is_whitespace) {
+    return None
+  }
+  let path = rest.trim().strip_prefix('"')?.strip_suffix('"')?;
+  if path.contains('"') { None } else { Some(path) }
+}
+
+/// The atoms named by an export directive, with their byte offsets in the
+/// text, if the text is one.
+fn export(text:&str) -> Option<Vec<(usize, &str)>> {
+  let rest = text.strip_prefix("export")?;
+  if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+    return None
+  }
+  let mut names = Vec::new();
+  let mut start = None;
+  for (i, c) in rest.char_indices().chain(std::iter::once((rest.len(), ' '))) {
+    if !c.is_whitespace() {
+      start = start.or(Some(i));
+    } else if let Some(start) = start.take() {
+      names.push((text.len() - rest.len() + start, &rest[start..i]));
+    }
+  }
+  Some(names)
+}
+
+/// Splits a source text into the spans of its rules and directives,
+/// checking that parentheses are balanced and that each rule has exactly one
+/// `=`.
+fn split(source:&str) -> (Vec<Range<usize>>, Vec<SyntaxError>) {
+  let mut rules = Vec::new();
+  let mut errors = Vec::new();
+  let mut start = None;
+  let mut opens = Vec::new();
+  let mut equals = None;
+  let mut valid = true;
+  let mut in_path = false;
+  for (i, c) in source.char_indices() {
+    if start.is_none() {
+      if c.is_whitespace() {
+        continue
+      }
+      start = Some(i);
+    }
+    if in_path {
+      in_path = c != '"';
+      continue
+    }
+    match c {
+      | '"' if source[start.unwrap_or(i)..i].trim_end() == "include" => in_path = true,
+      | '(' => opens.push(i),
+      | ')' =>
+        if opens.pop().is_none() {
+          errors.push(SyntaxError::new(i..i + 1, "unmatched `)`"));
+          valid = false;
+        },
+      | '=' if !opens.is_empty() => {
+        errors.push(SyntaxError::new(i..i + 1, "`=` inside a quote"));
+        valid = false;
+      },
+      | '=' if equals.is_some() => {
+        errors.push(SyntaxError::new(i..i + 1, "a rule can only have one `=`"));
+        valid = false;
+      },
+      | '=' => equals = Some(i),
+      | '.' => {
+        if let Some(&open) = opens.first() {
+          errors.push(SyntaxError::new(open..open + 1, "unclosed `(`"));
+        } else if equals.is_none()
+                  && include(&source[start.unwrap_or(i)..i]).is_none()
+                  && export(&source[start.unwrap_or(i)..i]).is_none()
+        {
+          errors.push(SyntaxError::new(i..i + 1, "expected `=` before the end of the rule"));
+        } else if valid {
+          rules.push(start.unwrap_or(i)..i + 1);
+        }
+        start = None;
+        opens.clear();
+        equals = None;
+        valid = true;
+      },
+      | _ => {},
+    }
+  }
+  if start.is_some() {
+    let end = source.trim_end().len();
+    errors.push(SyntaxError::new(end..end, "expected `.` at the end of the rule"));
+  }
+  (rules, errors)
+}
+
+/// The span of the first token in a rule that does not parse on its own, if
+/// there is one.
+fn bad_token(engine:&Engine, source:&str, rule:&Range<usize>) -> Option<Range<usize>> {
+  let text = &source[rule.clone()];
+  let mut token_start = None;
+  for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+    if c.is_whitespace() || "()=.".contains(c) {
+      if let Some(start) = token_start.take() {
+        if parse::terms(engine, &text[start..i]).is_err() {
+          return Some(rule.start + start..rule.start + i)
+        }
+      }
+    } else if token_start.is_none() {
+      token_start = Some(i);
+    }
+  }
+  None
+}
+
+/// Parses the rules and directives in a source text.
+///
+/// # Errors
+///
+/// Returns every syntax error found if any rule could not be parsed
+pub fn parse(engine:&Engine, source:&str) -> Result<Contents, Vec<SyntaxError>> {
+  let (spans, mut errors) = split(source);
+  let mut contents = Contents::default();
+  for span in spans {
+    let text = &source[span.start..span.end - 1];
+    if let Some(path) = include(text) {
+      contents.includes.push(Include { path:path.to_string(), span:Some(span) });
+      continue
+    }
+    if let Some(names) = export(text).filter(|_| !text.contains('=')) {
+      let exports = contents.exports.get_or_insert_with(Vec::new);
+      for (offset, name) in names {
+        match parse::term(engine, name) {
+          | Ok(term) => exports.push(pretty::term(engine, term)),
+          | Err(e) => {
+            let start = span.start + offset;
+            errors.push(SyntaxError::new(start..start + name.len(), e.to_string()));
+          },
+        }
+      }
+      continue
+    }
+    match parse::rules(engine, &source[span.clone()]) {
+      | Ok(parsed) =>
+        for rule in parsed {
+          contents.rules.push_back(rule);
+          contents.rule_spans.push(span.clone());
+        },
+      | Err(e) => {
+        let span = bad_token(engine, source, &span).unwrap_or(span);
+        errors.push(SyntaxError::new(span, e.to_string()));
+      },
+    }
+  }
+  if errors.is_empty() {
+    Ok(contents)
+  } else {
+    errors.sort_by_key(|error| error.span.start);
+    Err(errors)
+  }
+}
+
+/// Writes directives and rules as text.
+#[must_use]
+pub fn write(engine:&Engine, contents:Contents) -> String {
+  let mut text = contents.includes
+                         .iter()
+                         .map(|include| format!("include \"{}\".\n", include.path))
+                         .collect::<String>();
+  if let Some(exports) = &contents.exports {
+    text.push_str("export");
+    for export in exports {
+      text.push(' ');
+      text.push_str(export);
+    }
+    text.push_str(".\n");
+  }
+  text.push_str(&pretty::rules(engine, contents.rules));
+  text
+}
+
+#[cfg(test)]
+mod tests {
+  use mlatu_lib::{parse, Engine};
+
+  use super::SyntaxError;
+  use crate::format::Contents;
+
+  fn parse_text(source:&str) -> Result<Contents, Vec<SyntaxError>> {
+    super::parse(&Engine::new(), source)
+  }
+
+  fn errors(source:&str) -> Vec<(std::ops::Range<usize>, String)> {
+    parse_text(source).expect_err("the source is invalid")
+                      .into_iter()
+                      .map(|error| (error.span, error.message))
+                      .collect()
+  }
+
+  #[test]
+  fn agrees_with_the_parser() {
+    let engine = Engine::new();
+    for source in ["a = b.",
+                   "a (b c) = (c) b.\n\nd = .",
+                   "((a) (b)) = ((a)) b.  x y = y x.",
+                   "a\n=\n(b\n)\n.",
+                   "(=) = ~.",
+                   "a b c = (((c))) (b) ()."]
+    {
+      let rules = parse_text(source).map(|contents| contents.rules).ok();
+      assert_eq!(rules, parse::rules(&engine, source).ok(), "{:?}", source);
+    }
+  }
+
+  #[test]
+  fn spans_each_rule() {
+    let source = "a = b.\n  c = (d). include \"lib/nat.v2.mlt\".\nexport a c.";
+    let contents = parse_text(source).expect("the source is valid");
+    let spans = contents.rule_spans.iter().map(|span| &source[span.clone()]).collect::<Vec<_>>();
+    assert_eq!(spans, ["a = b.", "c = (d)."]);
+    assert_eq!(contents.includes[0].path, "lib/nat.v2.mlt");
+    assert_eq!(contents.exports, Some(vec!["a".to_string(), "c".to_string()]));
+  }
+
+  #[test]
+  fn reports_unbalanced_parentheses() {
+    assert_eq!(errors("a ) = b."), [(2..3, "unmatched `)`".to_string())]);
+    assert_eq!(errors("(a b) = c (d. x = y."), [(10..11, "unclosed `(`".to_string())]);
+    assert_eq!(errors("a (b = c."), [(2..3, "unclosed `(`".to_string()),
+                                     (5..6, "`=` inside a quote".to_string())]);
+  }
+
+  #[test]
+  fn reports_dots_and_equals_out_of_place() {
+    assert_eq!(errors("a = (b . c)."), [(4..5, "unclosed `(`".to_string()),
+                                        (10..11, "unmatched `)`".to_string()),
+                                        (11..12,
+                                         "expected `=` before the end of the rule".to_string())]);
+    assert_eq!(errors("a = b = c."), [(6..7, "a rule can only have one `=`".to_string())]);
+    assert_eq!(errors("a = b.\nc = d"), [(12..12,
+                                          "expected `.` at the end of the rule".to_string())]);
+  }
+}
@@ -1,22 +1,158 @@
+use std::fs::OpenOptions;
 use std::io;
-use std::io::stdout;
+use std::io::{stdout, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crossterm::event::KeyEvent;
 use crossterm::execute;
 use im::{vector, Vector};
-use mlatu_lib::{parse, pretty, rewrite, Engine, Rule, Term};
-use tokio::sync::RwLock;
+use mlatu_lib::{parse, pretty, Engine, Rule, Term};
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinHandle;
 
+use crate::eval::{Evaluation, Evaluator, Fired, Limits, Match, Outcome, Step, Strategy};
 use crate::view::{State, View};
+use crate::{pretty_terms, proof, Library};
+
+/// A derivation made by applying rules by hand, one step at a time.
+struct Manual {
+  /// The input when the derivation started, resolved
+  start:Vector<Term>,
+  steps:Vec<Step>,
+  /// The index of the selected match in the current program
+  selected:usize,
+  /// The index of the first match shown
+  scroll:usize,
+  /// What happened when the derivation was last saved
+  saved:Option<String>,
+}
+
+impl Manual {
+  fn new(start:Vector<Term>) -> Self {
+    Self { start, steps:Vec::new(), selected:0, scroll:0, saved:None }
+  }
+
+  /// The program the derivation has reached.
+  fn current(&self) -> &Vector<Term> { self.steps.last().map_or(&self.start, |step| &step.after) }
+
+  /// Scrolls the matches just enough for the selected one to be among the
+  /// `rows` shown.
+  fn follow(&mut self, rows:usize) {
+    if self.selected < self.scroll {
+      self.scroll = self.selected;
+    } else if self.selected >= self.scroll + rows {
+      self.scroll = self.selected + 1 - rows;
+    }
+  }
+}
+
+/// How far rewriting in the background has got.
+#[derive(Debug, Clone, Default)]
+struct Progress {
+  /// The program so far
+  terms:Vector<Term>,
+  /// A description of how far rewriting has got, or empty before the first
+  /// update
+  description:String,
+  /// The first steps, as many as the trace shows
+  steps:Vec<Step>,
+}
+
+/// Rewriting the input in the background.
+struct Task {
+  /// Set to stop rewriting
+  cancelled:Arc<AtomicBool>,
+  progress:watch::Receiver<Progress>,
+  handle:JoinHandle<(Evaluation, Vec<Step>)>,
+}
+
+impl Task {
+  /// Starts rewriting a program on a blocking thread, keeping the first
+  /// `traced` steps.
+  fn spawn(engine:&'static Engine, library:&'static Library, strategy:Strategy, limits:Limits,
+           terms:Vector<Term>, traced:usize)
+           -> Self {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let (sender, progress) =
+      watch::channel(Progress { terms:terms.clone(), ..Progress::default() });
+    let token = Arc::clone(&cancelled);
+    let handle = tokio::task::spawn_blocking(move || {
+      let evaluator = Evaluator::new(engine, library).with_strategy(strategy);
+      let mut evaluation = evaluator.start(&terms);
+      let mut steps = Vec::new();
+      let mut shown = Instant::now();
+      while !evaluation.is_finished() {
+        if token.load(Ordering::Relaxed) {
+          evaluation.cancel();
+          break
+        }
+        while steps.len() < traced {
+          match evaluator.next_step(&mut evaluation, &limits) {
+            | Some(step) => steps.push(step),
+            | None => break,
+          }
+        }
+        evaluator.advance(&mut evaluation, &limits, SLICE);
+        if shown.elapsed() >= PROGRESS {
+          shown = Instant::now();
+          let progress = Progress { terms:evaluation.terms.clone(),
+                                    description:evaluation.to_string(),
+                                    steps:steps.clone() };
+          // Nobody is waiting for the result once the receiver is dropped
+          if sender.send(progress).is_err() {
+            evaluation.cancel();
+          }
+        }
+      }
+      (evaluation, steps)
+    });
+    Self { cancelled, progress, handle }
+  }
+
+  fn cancel(&self) { self.cancelled.store(true, Ordering::Relaxed); }
+}
 
 pub struct Interactive {
-  rules:Vector<Rule>,
-  engine:Engine,
+  library:&'static Library,
+  engine:&'static Engine,
+  /// Rewrites with the library and the strategy in use
+  evaluator:Evaluator<'static>,
   view:View,
   should_quit:bool,
   state:State,
+  /// Whether the right pane shows the steps of rewriting the input
+  tracing:bool,
+  limits:Limits,
+  strategy:Strategy,
+  /// The seed the random strategy uses when it is chosen
+  seed:u64,
+  /// Whether rewriting waits for a keypress before each step, rather than
+  /// running to the end
+  stepping:bool,
+  /// The input that is being or was last rewritten
+  input:Vector<Term>,
+  /// Rewriting the input once it has finished, or while stepping
+  evaluation:Option<Evaluation>,
+  /// Rewriting the input in the background, when not stepping
+  task:Option<Task>,
+  /// The first steps of rewriting the input, as many as the trace shows
+  traced:Vec<Step>,
+  /// Why the input could not be resolved, if it could not
+  error:Option<String>,
+  /// The derivation being made by hand, if the right pane shows one
+  manual:Option<Manual>,
 }
 
+/// The number of steps taken between checking whether rewriting was
+/// cancelled.
+const SLICE:usize = 1000;
+
+/// How often the partial result of rewriting is shown.
+const PROGRESS:Duration = Duration::from_millis(100);
+
 fn die(e:&str) -> ! {
   std::mem::drop(execute!(stdout(),
                           crossterm::terminal::Clear(crossterm::terminal::ClearType::All)));
@@ -25,10 +161,16 @@ fn die(e:&str) -> ! {
 }
 
 impl Interactive {
+  /// Creates the interface. The engine and library are kept until the
+  /// program exits, so that rewriting in the background can use them.
+  ///
   /// # Errors
   ///
   /// Will return `Err` if there was an error constructing a terminal
-  pub fn new(engine:Engine, rules:Vector<Rule>) -> io::Result<Self> {
+  pub fn new(engine:Engine, library:Library, limits:Limits, strategy:Strategy, stepping:bool)
+             -> io::Result<Self> {
+    let engine:&'static Engine = Box::leak(Box::new(engine));
+    let library:&'static Library = Box::leak(Box::new(library));
     crossterm::terminal::enable_raw_mode()?;
     let should_quit = false;
     let rule = Arc::new(RwLock::new(Rule { redex:Vector::new(), reduction:Vector::new() }));
@@ -37,7 +179,26 @@ impl Interactive {
     let view = View::new(Arc::clone(&rule),
                          ("| Input |".to_string(), "| Output |".to_string()),
                          default_status)?;
-    Ok(Self { rules, engine, view, should_quit, state })
+    Ok(Self { library,
+              engine,
+              evaluator:Evaluator::new(engine, library).with_strategy(strategy),
+              view,
+              should_quit,
+              state,
+              tracing:false,
+              limits,
+              strategy,
+              seed:match strategy {
+                | Strategy::Random(seed) => seed,
+                | _ => rand::random(),
+              },
+              stepping,
+              input:Vector::new(),
+              evaluation:None,
+              task:None,
+              traced:Vec::new(),
+              error:None,
+              manual:None })
   }
 
   /// # Panics
@@ -50,8 +211,28 @@ impl Interactive {
   /// Returns `Err` if there was an IO error reading keypresses
   pub async fn run(&mut self) -> io::Result<()> {
     loop {
+      self.restart().await;
+      let label = match (&self.error, &self.task, &self.evaluation) {
+        | (Some(error), ..) => format!("| Output: {} |", error),
+        | (None, Some(task), _) if !task.progress.borrow().description.is_empty() =>
+          format!("| Output: {} |", task.progress.borrow().description),
+        | (None, None, Some(evaluation)) if evaluation.outcome != Some(Outcome::Normal) =>
+          format!("| Output: {} |", evaluation),
+        | _ => "| Output |".to_string(),
+      };
+      self.view.set_right_label(label);
+      let status = self.status();
+      self.view.set_default_status(status);
+      let pane = if self.manual.is_some() {
+        Some(("| Rewrite by hand |".to_string(), self.manual_pane()))
+      } else if self.tracing {
+        Some(("| Trace |".to_string(), self.trace()))
+      } else {
+        None
+      };
+      self.view.set_right_pane(pane);
       if let Err(error) = self.view
-                              .refresh_screen(|term| pretty::term(&self.engine, term.clone()),
+                              .refresh_screen(|term| pretty::term(self.engine, term.clone()),
                                               &self.state,
                                               self.should_quit)
                               .await
@@ -59,10 +240,307 @@ impl Interactive {
         die(&error.to_string());
       }
       if self.should_quit {
+        self.discard();
         let _result = crossterm::terminal::disable_raw_mode();
         break Ok(())
       }
-      self.process_keypress().await?;
+      // The screen is only drawn again once a key is pressed or rewriting in
+      // the background gets further or finishes
+      if let Some(task) = &mut self.task {
+        tokio::select! {
+          biased;
+          event = self.view.read_key() => self.process_keypress(event?).await,
+          finished = &mut task.handle => self.finish(finished).await,
+          Ok(()) = task.progress.changed() => self.show_progress().await,
+        }
+      } else {
+        let event = self.view.read_key().await?;
+        self.process_keypress(event).await;
+      }
+    }
+  }
+
+  /// Shows the partial result of rewriting in the background.
+  async fn show_progress(&mut self) {
+    if let Some(task) = &self.task {
+      let progress = task.progress.borrow().clone();
+      self.view.write().await.reduction = progress.terms;
+      self.traced = progress.steps;
+    }
+  }
+
+  /// Shows the result of rewriting in the background once it has finished.
+  async fn finish(&mut self, finished:Result<(Evaluation, Vec<Step>), tokio::task::JoinError>) {
+    self.task = None;
+    match finished {
+      | Ok((evaluation, steps)) => {
+        self.view.write().await.reduction = evaluation.terms.clone();
+        self.evaluation = Some(evaluation);
+        self.traced = steps;
+      },
+      | Err(e) => die(&e.to_string()),
+    }
+  }
+
+  /// Stops rewriting the input and drops the result, so that the input is
+  /// rewritten again.
+  fn discard(&mut self) {
+    if let Some(task) = self.task.take() {
+      task.cancel();
+    }
+    self.evaluation = None;
+    self.traced.clear();
+    self.error = None;
+  }
+
+  /// Starts rewriting the input again if it has changed, cancelling the
+  /// rewriting of the previous input, or if the strategy or stepping has and
+  /// so dropped the evaluation. Unless stepping, rewriting happens in the
+  /// background.
+  async fn restart(&mut self) {
+    let mut guard = self.view.write().await;
+    if guard.redex != self.input
+       || (self.evaluation.is_none() && self.task.is_none() && self.error.is_none())
+    {
+      if let Some(task) = self.task.take() {
+        task.cancel();
+      }
+      self.evaluation = None;
+      self.traced.clear();
+      self.error = None;
+      self.input = guard.redex.clone();
+      match self.library.resolve(self.engine, &self.input) {
+        | Ok(terms) if self.stepping => {
+          let evaluation = self.evaluator.start(&terms);
+          guard.reduction = evaluation.terms.clone();
+          self.evaluation = Some(evaluation);
+        },
+        | Ok(terms) => {
+          guard.reduction = terms.clone();
+          self.task = Some(Task::spawn(self.engine,
+                                       self.library,
+                                       self.strategy,
+                                       self.limits,
+                                       terms,
+                                       self.view.pane_height().max(1).into()));
+        },
+        | Err(message) => {
+          guard.reduction = Vector::new();
+          self.error = Some(message);
+        },
+      }
+    }
+  }
+
+  /// Rewrites the input by one step while stepping, showing the result so
+  /// far.
+  async fn advance(&mut self) {
+    if let Some(evaluation) = &mut self.evaluation {
+      let step = self.evaluator.next_step(evaluation, &self.limits);
+      self.view.write().await.reduction = evaluation.terms.clone();
+      if self.traced.len() < self.view.pane_height().into() {
+        self.traced.extend(step);
+      }
+    }
+  }
+
+  /// Switches to the next strategy, starting rewriting the input again.
+  fn next_strategy(&mut self) {
+    self.strategy = match self.strategy {
+      | Strategy::Leftmost => Strategy::Rightmost,
+      | Strategy::Rightmost => Strategy::Shortest,
+      | Strategy::Shortest => Strategy::Random(self.seed),
+      | Strategy::Random(_) => Strategy::Leftmost,
+    };
+    self.evaluator = Evaluator::new(self.engine, self.library).with_strategy(self.strategy);
+    self.discard();
+  }
+
+  /// The status line, giving the strategy and whether rewriting is stepping,
+  /// and naming the rules and primitives of the cycle rewriting the input
+  /// entered, if it did, or else the one that applies next.
+  fn status(&self) -> String {
+    let evaluator = &self.evaluator;
+    if let Some(manual) = &self.manual {
+      return format!("mlatu interface [by hand, {} step(s)] ENTER applies, BACKSPACE undoes, \
+                      CTRL-W saves{}",
+                     manual.steps.len(),
+                     manual.saved
+                           .as_ref()
+                           .map_or_else(String::new, |saved| format!(" ({})", saved)))
+    }
+    let mode = format!("mlatu interface [{}{}]",
+                       self.strategy,
+                       if self.stepping { ", stepping" } else { "" });
+    if let Some(involved) =
+      self.evaluation.as_ref().and_then(|evaluation| evaluator.describe_cycle(evaluation))
+    {
+      return format!("{} (cycle through {})", mode, involved)
+    }
+    let next = match &self.evaluation {
+      | Some(evaluation) if self.stepping && !evaluation.is_finished() =>
+        evaluator.next_match(&evaluation.terms).map(|found| found.fired),
+      | _ => self.traced.first().map(|step| step.fired),
+    };
+    match next {
+      | Some(fired) => format!("{} ({})", mode, evaluator.describe(fired)),
+      | None => mode,
+    }
+  }
+
+  /// The first steps of rewriting the input that have been taken, as many as
+  /// fit in the pane.
+  fn trace(&self) -> Vec<String> {
+    self.traced
+        .iter()
+        .enumerate()
+        .map(|(i, step)| {
+          format!("{}. {} at {}: {}",
+                  i + 1,
+                  self.evaluator.describe(step.fired),
+                  step.position,
+                  pretty_terms(self.engine, step.after.clone()))
+        })
+        .collect()
+  }
+
+  /// The matches in the program a derivation has reached.
+  fn manual_matches(&self, manual:&Manual) -> Vec<Match> {
+    self.evaluator.matches(manual.current())
+  }
+
+  /// The program a derivation has reached and the matches in it that fit in
+  /// the pane, scrolled to show the selected one, which is marked.
+  fn manual_pane(&mut self) -> Vec<String> {
+    // The program and a blank line come before the matches
+    let rows = usize::from(self.view.pane_height()).saturating_sub(2).max(1);
+    if let Some(manual) = &mut self.manual {
+      manual.follow(rows);
+    }
+    let manual = match &self.manual {
+      | Some(manual) => manual,
+      | None => return Vec::new(),
+    };
+    let evaluator = &self.evaluator;
+    let mut lines = vec![pretty_terms(self.engine, manual.current().clone()), String::new()];
+    let matches = self.manual_matches(manual);
+    if matches.is_empty() {
+      lines.push("normal form".to_string());
+    }
+    for (i, found) in matches.iter().enumerate().skip(manual.scroll).take(rows) {
+      let marker = if i == manual.selected { '>' } else { ' ' };
+      let rule = match found.fired {
+        | Fired::Rule(index) =>
+          format!(": {}", proof::rule_text(self.engine, &self.library.rules[index])),
+        | Fired::Primitive(_) => String::new(),
+      };
+      lines.push(format!("{} {}. {} at {}{}",
+                         marker,
+                         i + 1,
+                         evaluator.describe(found.fired),
+                         found.position,
+                         rule));
+    }
+    lines
+  }
+
+  /// Starts or stops rewriting by hand, starting from the input if it can be
+  /// resolved.
+  async fn toggle_manual(&mut self) {
+    if self.manual.take().is_none() {
+      let guard = self.view.read().await;
+      self.manual = self.library.resolve(self.engine, &guard.redex).ok().map(Manual::new);
+    }
+  }
+
+  /// Applies the selected match once.
+  fn apply_selected(&mut self) {
+    if let Some(manual) = &self.manual {
+      let evaluator = &self.evaluator;
+      if let Some(found) = evaluator.matches(manual.current()).get(manual.selected) {
+        let before = manual.current().clone();
+        let after = evaluator.apply(&before, found);
+        let step = Step { fired:found.fired, position:found.position, before, after };
+        if let Some(manual) = &mut self.manual {
+          manual.steps.push(step);
+          manual.selected = 0;
+          manual.saved = None;
+        }
+      }
+    }
+  }
+
+  /// Writes the derivation as a proof script, unless the file already
+  /// exists.
+  fn save_proof(&mut self, path:&str) {
+    if let Some(manual) = &self.manual {
+      let script = proof::write(self.engine, &self.evaluator, &manual.start, &manual.steps);
+      let written = OpenOptions::new().write(true)
+                                      .create_new(true)
+                                      .open(Path::new(path))
+                                      .and_then(|mut file| file.write_all(script.as_bytes()));
+      let saved = match written {
+        | Ok(()) => format!("saved to {}", path),
+        | Err(e) if e.kind() == io::ErrorKind::AlreadyExists =>
+          format!("could not save: {} already exists", path),
+        | Err(e) => format!("could not save: {}", e),
+      };
+      if let Some(manual) = &mut self.manual {
+        manual.saved = Some(saved);
+      }
+    }
+  }
+
+  /// Handles a keypress while rewriting by hand: the arrows select a match,
+  /// `ENTER` applies it, `BACKSPACE` undoes the last step, `CTRL-W` saves the
+  /// derivation and `ESC` or `CTRL-R` stops.
+  async fn process_manual_keypress(&mut self, event:KeyEvent) {
+    use crossterm::event::KeyCode::{Backspace, Char, Delete, Down, Enter, Esc, Up};
+    use crossterm::event::KeyModifiers;
+
+    if let State::SavingAs(path, state) = self.state.clone() {
+      match event.code {
+        | Enter => {
+          self.state = *state;
+          self.save_proof(&path);
+        },
+        | Esc => self.state = *state,
+        | Backspace | Delete => {
+          let mut path = path;
+          path.pop();
+          self.state = State::SavingAs(path, state);
+        },
+        | Char(c) => {
+          let mut path = path;
+          path.push(c);
+          self.state = State::SavingAs(path, state);
+        },
+        | _ => {},
+      }
+      return
+    }
+    let count = self.manual.as_ref().map_or(0, |manual| self.manual_matches(manual).len());
+    match (event.code, event.modifiers) {
+      | (Esc, _) | (Char('r'), KeyModifiers::CONTROL) => self.toggle_manual().await,
+      | (Char('w'), KeyModifiers::CONTROL) =>
+        self.state =
+          State::SavingAs(format!("proof.{}", proof::EXTENSION), Box::new(self.state.clone())),
+      | (Enter, _) => self.apply_selected(),
+      | (Backspace | Delete, _) =>
+        if let Some(manual) = &mut self.manual {
+          let _undone = manual.steps.pop();
+          manual.selected = 0;
+          manual.saved = None;
+        },
+      | (Up, _) =>
+        if let Some(manual) = &mut self.manual {
+          manual.selected = manual.selected.saturating_sub(1);
+        },
+      | (Down, _) =>
+        if let Some(manual) = &mut self.manual {
+          manual.selected = (manual.selected + 1).min(count.saturating_sub(1));
+        },
+      | _ => {},
     }
   }
 
@@ -74,21 +552,17 @@ impl Interactive {
     } else {
       State::InLeft(index.min(guard.redex.len() - 1))
     };
-    guard.reduction = rewrite(&self.engine, &self.rules, guard.redex.clone());
   }
 
   async fn quote(&mut self, index:usize) {
     let mut guard = self.view.write().await;
-    guard.redex[index] =
-      Term::make_quote(&self.engine, vector![guard.redex[index].clone()]).clone();
-    guard.reduction = rewrite(&self.engine, &self.rules, guard.redex.clone());
+    guard.redex[index] = Term::make_quote(self.engine, vector![guard.redex[index].clone()]).clone();
   }
 
   async fn swap(&mut self, index:usize) {
     let mut guard = self.view.write().await;
     if index > 0 {
       guard.redex.swap(index, index - 1);
-      guard.reduction = rewrite(&self.engine, &self.rules, guard.redex.clone());
     }
   }
 
@@ -96,7 +570,6 @@ impl Interactive {
     let mut guard = self.view.write().await;
     let term = guard.redex[index].clone();
     guard.redex.insert(index, term);
-    guard.reduction = rewrite(&self.engine, &self.rules, guard.redex.clone());
   }
 
   async fn concat(&mut self, index:usize) {
@@ -105,8 +578,7 @@ impl Interactive {
       if let Some(Term::Quote(other_terms)) = guard.redex.get(index + 1) {
         terms.extend(other_terms.clone());
         guard.redex.remove(index);
-        guard.redex[index] = Term::make_quote(&self.engine, terms).clone();
-        guard.reduction = rewrite(&self.engine, &self.rules, guard.redex.clone());
+        guard.redex[index] = Term::make_quote(self.engine, terms).clone();
       }
     }
   }
@@ -125,30 +597,49 @@ impl Interactive {
       } else {
         State::InLeft(index.min(guard.redex.len() - 1))
       };
-      guard.reduction = rewrite(&self.engine, &self.rules, guard.redex.clone());
     }
   }
 
-  async fn process_keypress(&mut self) -> io::Result<()> {
-    use crossterm::event::KeyCode::{Backspace, Char, Delete, Down, Esc, Up};
+  async fn process_keypress(&mut self, event:KeyEvent) {
+    use crossterm::event::KeyCode::{Backspace, Char, Delete, Down, Enter, Esc, Up};
+    use crossterm::event::KeyModifiers;
 
-    let event = self.view.read_key().await?;
+    if self.manual.is_some() {
+      return self.process_manual_keypress(event).await
+    }
+    let rewriting =
+      self.task.is_some()
+      || self.evaluation.as_ref().map_or(false, |evaluation| !evaluation.is_finished());
     match (event.code, event.modifiers) {
+      | (Char('r'), KeyModifiers::CONTROL) => self.toggle_manual().await,
+      | (Esc, _) | (Char('c'), KeyModifiers::CONTROL) if rewriting => {
+        if let Some(task) = &self.task {
+          task.cancel();
+        }
+        if let Some(evaluation) = &mut self.evaluation {
+          evaluation.cancel();
+        }
+      },
       | (Esc, _) => self.should_quit = true,
+      | (Char('t'), KeyModifiers::CONTROL) => self.tracing = !self.tracing,
+      | (Char('o'), KeyModifiers::CONTROL) => self.next_strategy(),
+      | (Char('p'), KeyModifiers::CONTROL) => {
+        self.stepping = !self.stepping;
+        self.discard();
+      },
+      | (Enter, _) if self.stepping && rewriting => self.advance().await,
       | (Char(c), _) => match (c, self.state.clone()) {
         | (' ', State::Editing(s, state)) =>
-          if let Ok(term) = parse::term(&self.engine, &s) {
+          if let Ok(term) = parse::term(self.engine, &s) {
             let mut guard = self.view.write().await;
             match *state {
               | State::AtLeft => {
                 guard.redex = vector![term.clone()];
                 self.state = State::InLeft(0);
-                guard.reduction = rewrite(&self.engine, &self.rules, guard.redex.clone());
               },
               | State::InLeft(index) => {
                 guard.redex.insert(index, term.clone());
                 self.state = State::InLeft(index);
-                guard.reduction = rewrite(&self.engine, &self.rules, guard.redex.clone());
               },
               | _ => {},
             }
@@ -202,6 +693,24 @@ impl Interactive {
       },
       | _ => {},
     }
-    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use im::Vector;
+
+  use super::Manual;
+
+  #[test]
+  fn scrolls_to_keep_the_selected_match_shown() {
+    let mut manual = Manual::new(Vector::new());
+    let mut scrolls = Vec::new();
+    for selected in [0, 3, 4, 6, 5, 2, 0] {
+      manual.selected = selected;
+      manual.follow(4);
+      scrolls.push(manual.scroll);
+    }
+    assert_eq!(scrolls, vec![0, 0, 1, 3, 3, 2, 0]);
   }
 }
@@ -0,0 +1,222 @@
+//! Running rules backwards to find programs that rewrite to a target, for
+//! `mlatu invert`.
+//!
+//! A step backwards replaces an occurrence of the reduction of a rule with its
+//! redex, or undoes a primitive: two equal quotes next to each other may come
+//! from copying, a quote holding a single quote from wrapping, any terms from
+//! unwrapping a quote of them, two quotes from swapping, and a quote from
+//! concatenating two parts of it. Removing cannot be undone, as what was
+//! removed could be anything. Programs are searched breadth-first from the
+//! target, and since running rules backwards ignores the order rewriting
+//! uses, each candidate is only kept if rewriting it forwards reaches the
+//! target.
+
+use std::collections::{HashSet, VecDeque};
+
+use im::{vector, Vector};
+use mlatu_lib::{Engine, Term};
+
+use crate::eval::{primitives, size, Evaluation, Evaluator, Limits, Primitive};
+
+/// How far searching goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+  /// The most steps backwards from the target
+  pub depth:usize,
+  /// The most candidates found before stopping
+  pub candidates:usize,
+  /// The most distinct programs visited
+  pub programs:usize,
+  /// The largest program that is searched further, counting the terms
+  /// inside quotes
+  pub size:usize,
+  /// The limits on rewriting each candidate forwards
+  pub limits:Limits,
+}
+
+impl Default for Bounds {
+  fn default() -> Self {
+    Self { depth:4,
+           candidates:20,
+           programs:10_000,
+           size:100,
+           limits:Limits { steps:Some(10_000), size:Some(10_000), time:None } }
+  }
+}
+
+/// A program that rewrites to the target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+  pub terms:Vector<Term>,
+  /// The number of steps backwards it was found at
+  pub depth:usize,
+  /// The number of steps rewriting it forwards takes to reach the target
+  pub steps:usize,
+}
+
+/// The result of searching backwards from a target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Search {
+  /// The candidates that rewrite to the target, closest first
+  pub candidates:Vec<Candidate>,
+  /// The number of programs found backwards that do not rewrite to the
+  /// target when rewritten forwards
+  pub rejected:usize,
+  /// Whether the search stopped at its bounds rather than running out of
+  /// programs
+  pub cut_off:bool,
+}
+
+/// Replaces a range of terms with others.
+fn splice(terms:&Vector<Term>, start:usize, len:usize, replacement:Vector<Term>) -> Vector<Term> {
+  let mut spliced = terms.clone();
+  let after = spliced.split_off(start).skip(len);
+  spliced.append(replacement);
+  spliced.append(after);
+  spliced
+}
+
+/// Every program one step backwards from a program.
+#[must_use]
+pub fn predecessors(engine:&Engine, evaluator:&Evaluator<'_>, terms:&Vector<Term>)
+                    -> Vec<Vector<Term>> {
+  let primitive = |wanted:Primitive| {
+    primitives(engine).into_iter().find(|(_, primitive)| *primitive == wanted).map(|(term, _)| term)
+  };
+  let quote = |terms:Vector<Term>| Term::make_quote(engine, terms).clone();
+  let mut found = Vec::new();
+  for rule in &evaluator.library().rules {
+    if rule.redex.is_empty() {
+      continue
+    }
+    let len = rule.reduction.len();
+    for start in 0..=terms.len().saturating_sub(len) {
+      if start + len <= terms.len() && terms.iter().skip(start).take(len).eq(rule.reduction.iter())
+      {
+        found.push(splice(terms, start, len, rule.redex.clone()));
+      }
+    }
+  }
+  for start in 0..terms.len() {
+    let quoted = match &terms[start] {
+      | Term::Quote(quoted) => quoted,
+      | _ => continue,
+    };
+    let next = terms.get(start + 1).filter(|term| matches!(term, Term::Quote(_)));
+    if let (Some(copy), Some(next)) = (primitive(Primitive::Copy), next) {
+      if *next == terms[start] {
+        found.push(splice(terms, start, 2, vector![terms[start].clone(), copy]));
+      }
+    }
+    if let (Some(swap), Some(next)) = (primitive(Primitive::Swap), next) {
+      found.push(splice(terms, start, 2, vector![next.clone(), terms[start].clone(), swap]));
+    }
+    if let (Some(wrap), Some(inner @ Term::Quote(_))) =
+      (primitive(Primitive::Wrap), quoted.get(0).filter(|_| quoted.len() == 1))
+    {
+      found.push(splice(terms, start, 1, vector![inner.clone(), wrap]));
+    }
+    if let Some(concat) = primitive(Primitive::Concat) {
+      for split in 0..=quoted.len() {
+        let mut left = quoted.clone();
+        let right = left.split_off(split);
+        found.push(splice(terms, start, 1, vector![quote(left), quote(right), concat.clone()]));
+      }
+    }
+  }
+  if let Some(unwrap) = primitive(Primitive::Unwrap) {
+    for start in 0..terms.len() {
+      for end in start + 1..=terms.len() {
+        let inside = terms.clone().slice(start..end);
+        found.push(splice(terms, start, end - start, vector![quote(inside), unwrap.clone()]));
+      }
+    }
+  }
+  found
+}
+
+/// The number of steps rewriting a program forwards takes to reach a target,
+/// if it does within some limits.
+fn reaches(evaluator:&Evaluator<'_>, terms:&Vector<Term>, target:&Vector<Term>, limits:&Limits)
+           -> Option<usize> {
+  let mut evaluation = Evaluation::new(terms.clone());
+  while evaluator.next_step(&mut evaluation, limits).is_some() {
+    if evaluation.terms == *target {
+      return Some(evaluation.steps)
+    }
+  }
+  None
+}
+
+/// Searches backwards from a resolved target for programs that rewrite to it.
+#[must_use]
+pub fn search(engine:&Engine, evaluator:&Evaluator<'_>, target:&Vector<Term>, bounds:&Bounds)
+              -> Search {
+  let target = target.clone();
+  let mut search = Search { candidates:Vec::new(), rejected:0, cut_off:false };
+  let mut seen = HashSet::new();
+  let mut queue = VecDeque::new();
+  let _new = seen.insert(target.clone());
+  queue.push_back((target.clone(), 0));
+  while let Some((terms, depth)) = queue.pop_front() {
+    if depth >= bounds.depth || size(&terms) > bounds.size {
+      search.cut_off = true;
+      continue
+    }
+    for predecessor in predecessors(engine, evaluator, &terms) {
+      if search.candidates.len() >= bounds.candidates || seen.len() >= bounds.programs {
+        search.cut_off = true;
+        return search
+      }
+      if !seen.insert(predecessor.clone()) {
+        continue
+      }
+      match reaches(evaluator, &predecessor, &target, &bounds.limits) {
+        | Some(steps) =>
+          search.candidates.push(Candidate { terms:predecessor.clone(), depth:depth + 1, steps }),
+        | None => search.rejected += 1,
+      }
+      queue.push_back((predecessor, depth + 1));
+    }
+  }
+  search
+}
+
+#[cfg(test)]
+mod tests {
+  use mlatu_lib::{parse, Engine};
+
+  use super::{predecessors, search, Bounds};
+  use crate::eval::{Evaluator, Strategy};
+  use crate::library::tests::parsed;
+
+  #[test]
+  fn undoes_rules_and_primitives() {
+    let engine = Engine::new();
+    let program = |text:&str| parse::terms(&engine, text).expect("the program parses");
+    let library = parsed(&engine, "a = x.");
+    let found = predecessors(&engine, &Evaluator::new(&engine, &library), &program("x (y)"));
+    for expected in ["a (y)", "x (y) () ,", "x () (y) ,", "(x) < (y)", "x ((y)) <"] {
+      assert!(found.contains(&program(expected)), "{}", expected);
+    }
+    assert!(!found.contains(&program("x (y) (y) -")));
+  }
+
+  #[test]
+  fn keeps_the_candidates_the_strategy_rewrites_to_the_target() {
+    let engine = Engine::new();
+    let program = |text:&str| parse::terms(&engine, text).expect("the program parses");
+    let library = parsed(&engine, "a = x. b = y. a b = ab.");
+    let bounds = Bounds { depth:2, candidates:1000, ..Bounds::default() };
+    let found = |strategy:Strategy| {
+      let evaluator = Evaluator::new(&engine, &library).with_strategy(strategy);
+      let search = search(&engine, &evaluator, &program("x y"), &bounds);
+      assert!(search.candidates.iter().all(|candidate| candidate.depth <= 2));
+      search.candidates.into_iter().map(|candidate| candidate.terms).collect::<Vec<_>>()
+    };
+    let leftmost = found(Strategy::Leftmost);
+    assert!(leftmost.contains(&program("a y")) && leftmost.contains(&program("x b")));
+    assert!(!leftmost.contains(&program("a b")));
+    assert!(found(Strategy::Shortest).contains(&program("a b")));
+  }
+}
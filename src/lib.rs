@@ -23,9 +23,39 @@
         clippy::verbose_file_reads)]
 #![allow(clippy::future_not_send)]
 
+pub mod analysis;
+pub mod conflict;
+mod convert;
+pub mod coverage;
+pub mod diagnostic;
 mod editor;
+pub mod eval;
+pub mod explore;
+pub mod format;
 mod interactive;
+pub mod inverse;
+pub mod library;
+mod loader;
+pub mod minimize;
+pub mod mutation;
+pub mod proof;
+pub mod property;
+mod repl;
+pub mod testing;
+pub mod trace;
 mod view;
 
+pub use convert::convert;
 pub use editor::Editor;
+use im::Vector;
 pub use interactive::Interactive;
+pub use library::Library;
+pub use loader::{load_file, load_files, read_file};
+use mlatu_lib::{pretty, Engine, Term};
+pub use repl::Repl;
+
+/// Pretty-prints a sequence of terms, separated by spaces.
+#[must_use]
+pub fn pretty_terms(engine:&Engine, terms:Vector<Term>) -> String {
+  terms.into_iter().map(|term| pretty::term(engine, term)).collect::<Vec<_>>().join(" ")
+}
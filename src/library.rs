@@ -0,0 +1,301 @@
+//! Loaded rules, along with the modules they came from.
+//!
+//! Every loaded file is a module named after its file stem, so the atoms of
+//! `nat.mlt` can be written `nat:succ` in other files and in programs. A file
+//! with an export list keeps its other defined atoms (those appearing in the
+//! redex of one of its rules) private: they are renamed to their qualified
+//! form, so they cannot interact with atoms of the same name elsewhere.
+//!
+//! The library also records the [`Origin`] of every rule, so that errors,
+//! warnings and the interfaces can point back to its source.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use im::Vector;
+use mlatu_lib::{parse, pretty, Engine, Rule, Term};
+
+use crate::diagnostic::Span;
+use crate::format::Contents;
+
+/// The separator between a module name and an atom in a qualified atom. It
+/// cannot be `.`, which ends a rule in text rule files, so that `nat.succ`
+/// would be read as the end of a rule followed by `succ`.
+pub const SEPARATOR:char = ':';
+
+/// The primitives, which no module can define.
+const PRIMITIVES:[&str; 6] = ["+", "-", ">", "<", "~", ","];
+
+#[derive(Clone)]
+struct Module {
+  path:PathBuf,
+  /// The exported atoms, or `None` if every atom is exported
+  exports:Option<HashSet<String>>,
+}
+
+impl Module {
+  fn exports(&self, atom:&str) -> bool {
+    self.exports.as_ref().map_or(true, |exports| exports.contains(atom))
+  }
+}
+
+/// Where a loaded rule was defined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+  pub path:PathBuf,
+  /// The location of the rule in text files
+  pub span:Option<Span>,
+  /// The first and last lines of the rule in text files, counting from 1
+  pub lines:Option<RangeInclusive<usize>>,
+  /// The index of the rule in its file, counting from 0
+  pub index:usize,
+}
+
+impl Origin {
+  /// The origins of the rules in a file, given its source text.
+  #[must_use]
+  pub fn of_rules(path:&Path, source:&str, contents:&Contents) -> Vec<Self> {
+    let mut origins = Vec::new();
+    for index in 0..contents.rules.len() {
+      let (span, lines) = match contents.rule_spans.get(index) {
+        | Some(bytes) => {
+          let span = Span::new(source, bytes.clone());
+          let text = source.get(bytes.clone()).unwrap_or("");
+          let last = span.line + text.trim_end().matches('\n').count();
+          let lines = span.line..=last;
+          (Some(span), Some(lines))
+        },
+        | None => (None, None),
+      };
+      origins.push(Self { path:path.to_path_buf(), span, lines, index });
+    }
+    origins
+  }
+}
+
+impl fmt::Display for Origin {
+  fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "rule {} of {}", self.index + 1, self.path.display())?;
+    if let Some(lines) = &self.lines {
+      write!(f, ":{}", lines.start())?;
+    }
+    Ok(())
+  }
+}
+
+/// Rules loaded from one or more files.
+#[derive(Clone, Default)]
+pub struct Library {
+  pub rules:Vector<Rule>,
+  /// The origin of every rule, in the same order
+  origins:Vec<Origin>,
+  modules:HashMap<String, Module>,
+}
+
+/// Replaces atoms in a sequence of terms, including inside quotes. `rename`
+/// returns the new name of an atom, or `None` to keep it.
+fn rename_atoms(engine:&Engine, terms:&Vector<Term>,
+                rename:&mut impl FnMut(&str) -> Result<Option<String>, String>)
+                -> Result<Vector<Term>, String> {
+  let mut renamed = Vector::new();
+  for term in terms {
+    renamed.push_back(match term {
+             | Term::Quote(terms) => {
+               let terms = rename_atoms(engine, terms, rename)?;
+               Term::make_quote(engine, terms).clone()
+             },
+             | _ => {
+               let name = pretty::term(engine, term.clone());
+               match rename(&name)? {
+                 | Some(name) => parse::term(engine, &name).map_err(|e| {
+                                                             format!("invalid atom '{}': {}",
+                                                                     name, e)
+                                                           })?,
+                 | None => term.clone(),
+               }
+             },
+           });
+  }
+  Ok(renamed)
+}
+
+fn rename_rules(engine:&Engine, rules:&Vector<Rule>,
+                rename:&mut impl FnMut(&str) -> Result<Option<String>, String>)
+                -> Result<Vector<Rule>, Vec<String>> {
+  let mut renamed = Vector::new();
+  let mut errors = Vec::new();
+  for rule in rules {
+    match (rename_atoms(engine, &rule.redex, rename), rename_atoms(engine, &rule.reduction, rename))
+    {
+      | (Ok(redex), Ok(reduction)) => renamed.push_back(Rule { redex, reduction }),
+      | (redex, reduction) => errors.extend(redex.err().into_iter().chain(reduction.err())),
+    }
+  }
+  if errors.is_empty() { Ok(renamed) } else { Err(errors) }
+}
+
+/// The atoms appearing in a sequence of terms, including inside quotes.
+fn atoms(engine:&Engine, terms:&Vector<Term>, found:&mut HashSet<String>) {
+  for term in terms {
+    match term {
+      | Term::Quote(terms) => atoms(engine, terms, found),
+      | _ => {
+        let _new = found.insert(pretty::term(engine, term.clone()));
+      },
+    }
+  }
+}
+
+impl Library {
+  /// Adds the rules of a file as a module, resolving the qualified atoms in
+  /// them and making the atoms the file defines but does not export private.
+  /// The source text of text files is used to record where each rule came
+  /// from.
+  ///
+  /// # Errors
+  ///
+  /// Returns a message for each qualified atom that names an unknown module
+  /// or an atom its module does not export, or if another file with an
+  /// export list has the same module name
+  pub fn add(&mut self, engine:&Engine, path:&Path, source:&str, contents:Contents)
+             -> Result<(), Vec<String>> {
+    let name =
+      path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+    let origins = Origin::of_rules(path, source, &contents);
+    let mut rules = rename_rules(engine, &contents.rules, &mut |atom| {
+      let (module, atom) = match atom.split_once(SEPARATOR) {
+        | Some(qualified) => qualified,
+        | None => return Ok(None),
+      };
+      if module == name {
+        return Ok(Some(atom.to_string()))
+      }
+      match self.modules.get(module) {
+        | Some(found) if found.exports(atom) => Ok(Some(atom.to_string())),
+        | Some(_) => Err(format!("'{}' is not exported by module '{}'", atom, module)),
+        | None => Err(format!("unknown module '{}'", module)),
+      }
+    })?;
+    if let Some(exports) = &contents.exports {
+      let mut defined = HashSet::new();
+      for rule in &rules {
+        atoms(engine, &rule.redex, &mut defined);
+      }
+      let private =
+        defined.into_iter()
+               .filter(|atom| !exports.contains(atom) && !PRIMITIVES.contains(&atom.as_str()))
+               .collect::<HashSet<_>>();
+      rules = rename_rules(engine, &rules, &mut |atom| {
+        Ok(private.contains(atom).then(|| format!("{}{}{}", name, SEPARATOR, atom)))
+      })?;
+    }
+    match self.modules.get(&name) {
+      | Some(module) if module.exports.is_some() || contents.exports.is_some() =>
+        return Err(vec![format!("module '{}' is already loaded from '{}'",
+                                name,
+                                module.path.display())]),
+      | Some(_) => {},
+      | None => {
+        let exports = contents.exports.map(|exports| exports.into_iter().collect());
+        let _previous = self.modules.insert(name, Module { path:path.to_path_buf(), exports });
+      },
+    }
+    self.origins.extend(origins);
+    self.rules.extend(rules);
+    Ok(())
+  }
+
+  /// A copy of the library with the rule at an index replaced, or removed
+  /// along with its origin if there is no replacement.
+  #[must_use]
+  pub fn with_rule(&self, index:usize, rule:Option<Rule>) -> Self {
+    let mut library = self.clone();
+    match rule {
+      | Some(rule) => library.rules[index] = rule,
+      | None => {
+        let _removed = library.rules.remove(index);
+        if index < library.origins.len() {
+          let _removed = library.origins.remove(index);
+        }
+      },
+    }
+    library
+  }
+
+  /// Where the rule at an index was defined.
+  #[must_use]
+  pub fn origin(&self, index:usize) -> Option<&Origin> { self.origins.get(index) }
+
+  /// Resolves the qualified atoms in a program written by a user to the atoms
+  /// of loaded modules they name. Other atoms are left as they are. Programs
+  /// built from the rules themselves, whose private atoms are already
+  /// qualified, must not be resolved again.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Err` if a qualified atom names an unknown module or an atom its
+  /// module does not export
+  pub fn resolve(&self, engine:&Engine, terms:&Vector<Term>) -> Result<Vector<Term>, String> {
+    rename_atoms(engine, terms, &mut |atom| {
+      let (module, atom) = match atom.split_once(SEPARATOR) {
+        | Some(qualified) => qualified,
+        | None => return Ok(None),
+      };
+      match self.modules.get(module) {
+        | Some(found) if found.exports(atom) => Ok(Some(atom.to_string())),
+        | Some(_) => Err(format!("'{}' is not exported by module '{}'", atom, module)),
+        | None => Err(format!("unknown module '{}'", module)),
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use std::path::Path;
+
+  use mlatu_lib::{parse, Engine};
+
+  use super::Library;
+  use crate::format::Contents;
+
+  /// A library of the rules in some text, loaded as one module.
+  pub(crate) fn parsed(engine:&Engine, rules:&str) -> Library {
+    let rules = parse::rules(engine, rules).expect("the rules parse");
+    let mut library = Library::default();
+    library.add(engine, Path::new("rules.mlt"), "", Contents { rules, ..Contents::default() })
+           .expect("the rules load");
+    library
+  }
+
+  #[test]
+  fn resolves_only_exported_atoms() {
+    let engine = Engine::new();
+    let rules = parse::rules(&engine, "succ = helper. helper = one.").expect("the rules parse");
+    let contents =
+      Contents { exports:Some(vec!["succ".to_string()]), rules, ..Contents::default() };
+    let mut library = Library::default();
+    library.add(&engine, Path::new("nat.mlt"), "", contents).expect("the module loads");
+    let program = |text:&str| parse::terms(&engine, text).expect("the program parses");
+    assert_eq!(library.resolve(&engine, &program("nat:succ")), Ok(program("succ")));
+    assert_eq!(library.resolve(&engine, &program("other:succ")),
+               Err("unknown module 'other'".to_string()));
+    assert_eq!(library.resolve(&engine, &program("(nat:helper)")),
+               Err("'helper' is not exported by module 'nat'".to_string()));
+  }
+
+  #[test]
+  fn removes_the_origin_with_the_rule() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = x. b = y. c = z.");
+    let mutated = library.with_rule(1, None);
+    assert_eq!(mutated.rules.len(), 2);
+    assert_eq!(mutated.origin(1).map(|origin| origin.index), Some(2));
+    assert_eq!(mutated.origin(2), None);
+    let replaced = library.with_rule(1, mutated.rules.get(1).cloned());
+    assert_eq!(replaced.rules.get(1), mutated.rules.get(1));
+    assert_eq!(replaced.origin(1), library.origin(1));
+  }
+}
@@ -0,0 +1,299 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use im::vector;
+use mlatu_lib::{pretty, Engine};
+
+use crate::conflict::{conflicts, Conflict, Overlap};
+use crate::diagnostic::{Diagnostic, Error, Kind, Span};
+use crate::format::{self, Contents, Format};
+use crate::library::{Library, Origin};
+
+/// Reads a file in any supported format without resolving its includes,
+/// also returning the format the file was in and its source text for
+/// locating errors.
+fn read(engine:&Engine, path:&Path) -> Result<(Contents, Format, String), Error> {
+  let bytes = std::fs::read(path).map_err(|e| {
+                Diagnostic::new(Kind::Read, format!("could not read file: {}", e)).with_path(path)
+              })?;
+  let format = Format::detect(path, &bytes);
+  let source = match format {
+    | Format::Binary => String::new(),
+    | Format::Text | Format::Json => String::from_utf8_lossy(&bytes).into_owned(),
+  };
+  match format.read(engine, bytes) {
+    | Ok(contents) => Ok((contents, format, source)),
+    | Err(format::Error::Decode { message, offset, }) => {
+      let diagnostic =
+        Diagnostic::new(Kind::Decode, format!("could not decode file: {}", message)).with_path(path);
+      Err(match offset {
+            | Some(offset) => diagnostic.with_span(&source, offset..offset + 1),
+            | None => diagnostic,
+          }.into())
+    },
+    | Err(format::Error::Parse(errors)) => Err(errors.into_iter()
+                                                     .map(|error| {
+                                                       Diagnostic::new(Kind::Parse, error.message)
+                                                       .with_path(path)
+                                                       .with_span(&source, error.span)
+                                                     })
+                                                     .collect::<Vec<_>>()
+                                                     .into()),
+  }
+}
+
+/// Reads the directives and rules from a file in any supported format,
+/// without loading the files it includes, also returning the format the file
+/// was in and the origin of each rule.
+///
+/// # Errors
+///
+/// Returns `Err` if the file could not be read, decoded or parsed, with a
+/// diagnostic for every error found
+pub fn read_file(engine:&Engine, path:&Path) -> Result<(Contents, Format, Vec<Origin>), Error> {
+  read(engine, path).map(|(contents, format, source)| {
+                      let origins = Origin::of_rules(path, &source, &contents);
+                      (contents, format, origins)
+                    })
+}
+
+/// Loads rule files and the files they include, each at most once.
+struct Loader<'a> {
+  engine:&'a Engine,
+  /// The directories in `MLATU_PATH`, searched for included files that are
+  /// not found relative to the file including them
+  search_path:Vec<PathBuf>,
+  /// The canonical paths of the files loaded so far
+  loaded:HashSet<PathBuf>,
+  /// The files currently being loaded, each included by the one before it
+  stack:Vec<PathBuf>,
+  library:Library,
+  diagnostics:Vec<Diagnostic>,
+}
+
+impl<'a> Loader<'a> {
+  fn new(engine:&'a Engine) -> Self {
+    let search_path = std::env::var_os("MLATU_PATH").map_or_else(Vec::new, |paths| {
+                                                      std::env::split_paths(&paths).collect()
+                                                    });
+    Self { engine,
+           search_path,
+           loaded:HashSet::new(),
+           stack:Vec::new(),
+           library:Library::default(),
+           diagnostics:Vec::new() }
+  }
+
+  /// Finds an included file, first relative to the directory of the file
+  /// including it and then in each directory of the search path.
+  fn resolve(&self, including:&Path, include:&str) -> Option<PathBuf> {
+    let base = including.parent().unwrap_or_else(|| Path::new(""));
+    std::iter::once(base).chain(self.search_path.iter().map(PathBuf::as_path))
+                         .map(|dir| dir.join(include))
+                         .find(|path| path.is_file())
+  }
+
+  /// Loads a file after the files it includes, unless it has already been
+  /// loaded. The notes describe the chain of includes that led to it.
+  fn load(&mut self, path:&Path, notes:&[String]) {
+    let with_notes = |mut diagnostic:Diagnostic| {
+      for note in notes {
+        diagnostic = diagnostic.with_note(note.clone());
+      }
+      diagnostic
+    };
+    let canonical = match std::fs::canonicalize(path) {
+      | Ok(canonical) => canonical,
+      | Err(e) => {
+        self.diagnostics.push(with_notes(Diagnostic::new(Kind::Read,
+                                                         format!("could not read file: {}", e))
+                                         .with_path(path)));
+        return
+      },
+    };
+    if !self.loaded.insert(canonical.clone()) {
+      return
+    }
+    let (contents, source) = match read(self.engine, path) {
+      | Ok((contents, _, source)) => (contents, source),
+      | Err(error) => {
+        self.diagnostics.extend(error.diagnostics.into_iter().map(with_notes));
+        return
+      },
+    };
+    self.stack.push(canonical);
+    for include in &contents.includes {
+      let site = |diagnostic:Diagnostic| {
+        let diagnostic = diagnostic.with_path(path);
+        with_notes(match &include.span {
+          | Some(span) => diagnostic.with_span(&source, span.clone()),
+          | None => diagnostic,
+        })
+      };
+      let included = match self.resolve(path, &include.path) {
+        | Some(included) => included,
+        | None => {
+          self.diagnostics
+              .push(site(Diagnostic::new(Kind::Read,
+                                         format!("could not find included file '{}'",
+                                                 include.path))));
+          continue
+        },
+      };
+      let canonical = std::fs::canonicalize(&included).unwrap_or_else(|_| included.clone());
+      if let Some(start) = self.stack.iter().position(|loading| *loading == canonical) {
+        let cycle = self.stack[start..].iter()
+                                       .chain(std::iter::once(&canonical))
+                                       .map(|path| path.display().to_string())
+                                       .collect::<Vec<_>>()
+                                       .join(" -> ");
+        self.diagnostics
+            .push(site(Diagnostic::new(Kind::Read, format!("include cycle: {}", cycle))));
+        continue
+      }
+      let mut included_from = notes.to_vec();
+      let location = match &include.span {
+        | Some(span) => {
+          let span = Span::new(&source, span.clone());
+          format!("{}:{}:{}", path.display(), span.line, span.column)
+        },
+        | None => path.display().to_string(),
+      };
+      included_from.insert(0, format!("included from {}", location));
+      self.load(&included, &included_from);
+    }
+    let _loaded = self.stack.pop();
+    if let Err(messages) = self.library.add(self.engine, path, &source, contents) {
+      for message in messages {
+        self.diagnostics.push(with_notes(Diagnostic::new(Kind::Parse, message).with_path(path)));
+      }
+    }
+  }
+
+  /// A warning about two rules that conflict.
+  fn conflict(&self, conflict:Conflict) -> Diagnostic {
+    let rule = |index:usize| {
+      pretty::rules(self.engine, vector![self.library.rules[index].clone()]).trim_end().to_string()
+    };
+    let (at, other, message, note) = match conflict.overlap {
+      | Overlap::Ambiguous => (conflict.second,
+                               conflict.first,
+                               format!("rule `{}` has the same pattern as `{}` but a different \
+                                        replacement",
+                                       rule(conflict.second),
+                                       rule(conflict.first)),
+                               "the other rule is"),
+      | Overlap::Duplicate => (conflict.second,
+                               conflict.first,
+                               format!("rule `{}` is defined more than once",
+                                       rule(conflict.second)),
+                               "it is also"),
+      | Overlap::Shadowed => (conflict.first,
+                              conflict.second,
+                              format!("rule `{}` is shadowed by `{}` wherever both match",
+                                      rule(conflict.first),
+                                      rule(conflict.second)),
+                              "the longer rule is"),
+    };
+    let mut diagnostic = Diagnostic::new(Kind::Conflict, message).as_warning();
+    if let Some(origin) = self.library.origin(at) {
+      diagnostic = diagnostic.with_origin(origin);
+    }
+    if let Some(origin) = self.library.origin(other) {
+      diagnostic = diagnostic.with_note(format!("{} {}", note, origin));
+    }
+    diagnostic
+  }
+}
+
+/// Loads the rules from a file in any supported format, after the rules of
+/// the files it includes.
+///
+/// # Errors
+///
+/// Returns `Err` if the file or any file it includes could not be loaded
+pub fn load_file(engine:&Engine, path:&Path) -> Result<(Library, Vec<Diagnostic>), Error> {
+  load_files(engine, vec![path.to_string_lossy().into_owned()])
+}
+
+/// Loads the rules from several files, in order.
+///
+/// The rules of the files a file includes come before its own, and every file
+/// is loaded at most once, however many times it is included. Each file
+/// becomes a module of the library. Also returns a warning for every pair of
+/// rules that conflict, naming where both were defined.
+///
+/// # Errors
+///
+/// Returns `Err` if any of the files could not be loaded, with the
+/// diagnostics for all of them
+pub fn load_files(engine:&Engine, files:Vec<String>) -> Result<(Library, Vec<Diagnostic>), Error> {
+  let mut loader = Loader::new(engine);
+  for file in files {
+    loader.load(Path::new(&file), &[]);
+  }
+  if loader.diagnostics.is_empty() {
+    let warnings = conflicts(&loader.library.rules).into_iter()
+                                                   .map(|conflict| loader.conflict(conflict))
+                                                   .collect();
+    Ok((loader.library, warnings))
+  } else {
+    Err(loader.diagnostics.into())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use mlatu_lib::{parse, Engine};
+
+  use super::load_file;
+
+  /// A fresh directory holding the given files.
+  fn files(name:&str, files:&[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mlatu-loader-{}-{}", name, std::process::id()));
+    let _removed = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("the directory can be created");
+    for (file, source) in files {
+      std::fs::write(dir.join(file), source).expect("the file can be written");
+    }
+    dir
+  }
+
+  #[test]
+  fn reports_include_cycles() {
+    let dir = files("cycle", &[("a.mlt", "include \"b.mlt\".\na = x."),
+                               ("b.mlt", "include \"c.mlt\".\nb = y."),
+                               ("c.mlt", "include \"a.mlt\".\nc = z.")]);
+    let error = load_file(&Engine::new(), &dir.join("a.mlt")).err().expect("the cycle is reported");
+    assert_eq!(error.diagnostics.len(), 1);
+    let diagnostic = &error.diagnostics[0];
+    let canonical = |file:&str| dir.join(file).canonicalize().expect("the file exists");
+    assert_eq!(diagnostic.message,
+               format!("include cycle: {} -> {} -> {} -> {}",
+                       canonical("a.mlt").display(),
+                       canonical("b.mlt").display(),
+                       canonical("c.mlt").display(),
+                       canonical("a.mlt").display()));
+    assert_eq!(diagnostic.path, Some(dir.join("c.mlt")));
+    assert_eq!(diagnostic.span.as_ref().map(|span| span.line), Some(1));
+    assert_eq!(diagnostic.notes.len(), 2);
+    let _removed = std::fs::remove_dir_all(dir);
+  }
+
+  #[test]
+  fn loads_each_file_once() {
+    let dir = files("diamond", &[("top.mlt",
+                                  "include \"left.mlt\".\ninclude \"right.mlt\".\ntop = l r."),
+                                 ("left.mlt", "include \"base.mlt\".\nl = b."),
+                                 ("right.mlt", "include \"base.mlt\".\nr = b."),
+                                 ("base.mlt", "b = .")]);
+    let engine = Engine::new();
+    let (library, warnings) = load_file(&engine, &dir.join("top.mlt")).expect("the files load");
+    assert!(warnings.is_empty());
+    assert_eq!(library.rules,
+               parse::rules(&engine, "b = . l = b. r = b. top = l r.").expect("rules parse"));
+    let _removed = std::fs::remove_dir_all(dir);
+  }
+}
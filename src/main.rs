@@ -1,70 +1,874 @@
 #![feature(with_options)]
 
+use std::fmt::Display;
+use std::io::{self, stdout, BufRead, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
-use clap::{arg, command, Command};
-use im::Vector;
-use mlatu::{Editor, Interactive};
-use mlatu_lib::{parse, Engine, Rule};
-
-fn load_file(engine:&Engine, filename:&str) -> Result<Vector<Rule>, String> {
-  let path = Path::new(&filename);
-  match std::fs::read(path) {
-    | Ok(bytes) => match String::from_utf8(bytes) {
-      | Ok(string) => match parse::rules(engine, &string) {
-        | Ok(rules) => Ok(rules),
-        | Err(e) => Err(format!("Error while parsing '{}': {}", filename, e)),
-      },
-      | Err(e) => Err(format!("Error in decoding '{}': {}", filename, e)),
+use clap::{arg, command, ArgMatches, Command};
+use crossterm::tty::IsTty;
+use im::{vector, Vector};
+use mlatu::analysis::completion::{self, Order};
+use mlatu::analysis::confluence;
+use mlatu::analysis::termination::{self, Termination};
+use mlatu::coverage::{self, Coverage};
+use mlatu::diagnostic::{Diagnostic, Error, Kind, Severity};
+use mlatu::eval::{Evaluation, Evaluator, Fired, Limits, Outcome, Strategy};
+use mlatu::explore::{self, Bounds};
+use mlatu::format::{Contents, Format};
+use mlatu::minimize::{self, Predicate};
+use mlatu::property::{self, Config, Generator};
+use mlatu::testing::{Case, Suite};
+use mlatu::{convert, inverse, load_files, mutation, pretty_terms, proof, read_file, testing, trace, Editor, Interactive, Library, Repl};
+use mlatu_lib::{parse, pretty, Engine, Term};
+
+/// Parses a program given on the command line and resolves its qualified
+/// atoms against a library.
+fn parse_program(engine:&Engine, library:&Library, program:&str)
+                 -> Result<Vector<Term>, Diagnostic> {
+  parse::terms(engine, program).map_err(|e| format!("could not parse program: {}", e))
+                               .and_then(|terms| library.resolve(engine, &terms))
+                               .map_err(|message| {
+                                 Diagnostic::new(Kind::Parse, message).with_span(program,
+                                                                                 0..program.len())
+                               })
+}
+
+/// Calls `run_one` with each program given with `-e`, or otherwise with each
+/// non-empty line of standard input, reporting the programs that fail.
+fn each_program(programs:Option<Vec<String>>,
+                mut run_one:impl FnMut(&str) -> Result<(), Diagnostic>)
+                -> Result<(), Error> {
+  let mut failures = 0_usize;
+  let mut kind = Kind::Parse;
+  let mut run_one = |program:&str| {
+    if let Err(diagnostic) = run_one(program) {
+      eprintln!("{}", diagnostic);
+      if failures == 0 {
+        kind = diagnostic.kind;
+      }
+      failures += 1;
+    }
+  };
+  if let Some(programs) = programs {
+    programs.iter().for_each(|program| run_one(program));
+  } else {
+    for line in io::stdin().lock().lines() {
+      let line = line.map_err(|e| {
+                       Diagnostic::new(Kind::Read, format!("could not read standard input: {}", e))
+                     })?;
+      if !line.trim().is_empty() {
+        run_one(&line);
+      }
+    }
+  }
+  if failures == 0 {
+    Ok(())
+  } else {
+    Err(Diagnostic::new(kind, format!("{} program(s) failed", failures)).into())
+  }
+}
+
+/// Adds the arguments for choosing the evaluation order.
+fn with_strategy(command:Command<'static>) -> Command<'static> {
+  command.arg(arg!(--strategy <STRATEGY>).required(false)
+                                         .possible_values(Strategy::NAMES)
+                                         .default_value("leftmost")
+                                         .help("Which match to rewrite first"))
+         .arg(arg!(--seed <SEED>).required(false)
+                                 .help("Seed for the random strategy (otherwise random)"))
+}
+
+/// Adds the arguments for the rule files to use and the programs to rewrite.
+fn with_programs(command:Command<'static>) -> Command<'static> {
+  with_strategy(with_inputs(command))
+}
+
+/// Adds the arguments for the rule files to use and the programs to work on,
+/// without choosing an evaluation order.
+fn with_inputs(command:Command<'static>) -> Command<'static> {
+  command.arg(arg!([FILES]).multiple_values(true).help("Rule files to use"))
+         .arg(arg!(-e --eval <PROGRAM>).required(false)
+                                       .multiple_occurrences(true)
+                                       .help("Program to rewrite (otherwise read from stdin)"))
+}
+
+/// Adds the arguments for the test files to run and the tests to select.
+fn with_tests(command:Command<'static>) -> Command<'static> {
+  command.arg(arg!(<FILES>).multiple_values(true)
+                           .help("Test files (.mltest) and rule files they all use"))
+         .arg(arg!(--filter <PATTERN>).required(false)
+                                      .multiple_occurrences(true)
+                                      .help("Only run tests whose name contains PATTERN"))
+}
+
+/// Adds the arguments for checking properties with generated programs.
+fn with_properties(command:Command<'static>) -> Command<'static> {
+  command.arg(arg!(--cases <N>).required(false).help("Check each property with N generated \
+                                                      programs [default: 100]"))
+         .arg(arg!(--depth <N>).required(false)
+                               .help("Nest generated quotes at most N deep [default: 2]"))
+         .arg(arg!(--seed <SEED>).required(false)
+                                 .help("Seed for generating programs (otherwise random)"))
+}
+
+fn files(matches:&ArgMatches) -> Vec<String> {
+  matches.values_of("FILES").map_or_else(Vec::new, |files| files.map(ToOwned::to_owned).collect())
+}
+
+/// The value of an argument clap requires.
+///
+/// # Panics
+///
+/// Panics if the argument is missing, which clap rules out by failing with a
+/// usage error first
+fn required<'a>(matches:&'a ArgMatches, name:&str) -> &'a str {
+  matches.value_of(name).expect("required by clap")
+}
+
+fn programs(matches:&ArgMatches) -> Option<Vec<String>> {
+  matches.values_of("eval").map(|programs| programs.map(ToOwned::to_owned).collect())
+}
+
+/// The limits on rewriting given on the command line. `0` means unlimited.
+fn limits(matches:&ArgMatches) -> Result<Limits, Error> {
+  let value = |name:&str, default:Option<usize>| {
+    Ok::<_, Error>(match number(matches, name)? {
+      | Some(0) => None,
+      | Some(value) => Some(value),
+      | None => default,
+    })
+  };
+  let defaults = Limits::default();
+  Ok(Limits { steps:value("max-steps", defaults.steps)?,
+              size:value("max-size", defaults.size)?,
+              time:number(matches, "timeout")?.map(Duration::from_millis) })
+}
+
+/// The value of a numeric option, if it was given.
+fn number<T:FromStr>(matches:&ArgMatches, name:&str) -> Result<Option<T>, Error>
+  where T::Err: Display, {
+  matches.value_of(name)
+         .map(|value| {
+           value.parse().map_err(|e| {
+                          Diagnostic::new(Kind::Other,
+                                          format!("invalid value '{}' for --{}: {}",
+                                                  value, name, e)).into()
+                        })
+         })
+         .transpose()
+}
+
+/// The evaluation order given on the command line.
+fn strategy(matches:&ArgMatches) -> Result<Strategy, Error> {
+  let seed = number(matches, "seed")?.unwrap_or_else(rand::random);
+  Ok(matches.value_of("strategy")
+            .and_then(|name| Strategy::from_name(name, seed))
+            .unwrap_or_default())
+}
+
+/// How properties are checked, from the command line. Rewriting generated
+/// programs has lower limits than usual unless they are given explicitly.
+fn property_config(matches:&ArgMatches, limits:&Limits) -> Result<Config, Error> {
+  let defaults = Config::default();
+  Ok(Config { cases:number(matches, "cases")?.unwrap_or(defaults.cases),
+              depth:number(matches, "depth")?.unwrap_or(defaults.depth),
+              seed:number(matches, "seed")?.unwrap_or(defaults.seed),
+              limits:Limits { steps:if matches.is_present("max-steps") {
+                                limits.steps
+                              } else {
+                                defaults.limits.steps
+                              },
+                              size:if matches.is_present("max-size") {
+                                limits.size
+                              } else {
+                                defaults.limits.size
+                              },
+                              time:limits.time },
+              ..defaults })
+}
+
+/// The diagnostic for a program that did not reach its normal form.
+fn unfinished(evaluator:&Evaluator<'_>, engine:&Engine, program:&str, evaluation:&Evaluation)
+              -> Diagnostic {
+  let result = pretty_terms(engine, evaluation.terms.clone());
+  let diagnostic =
+    Diagnostic::new(Kind::Limit, evaluation.to_string()).with_span(program, 0..program.len())
+                                                        .with_note(format!("partial result: {}",
+                                                                           result));
+  match evaluator.describe_cycle(evaluation) {
+    | Some(involved) => diagnostic.with_note(format!("the cycle goes through {}", involved)),
+    | None => diagnostic,
+  }
+}
+
+/// Loads rule files, printing a warning for each pair of conflicting rules,
+/// or failing if there are any when `strict` is set.
+fn load(engine:&Engine, files:Vec<String>, strict:bool) -> Result<Library, Error> {
+  let (library, warnings) = load_files(engine, files)?;
+  if strict && !warnings.is_empty() {
+    return Err(warnings.into_iter()
+                       .map(|warning| Diagnostic { severity:Severity::Error, ..warning })
+                       .collect::<Vec<_>>()
+                       .into())
+  }
+  for warning in warnings {
+    eprintln!("{}", warning);
+  }
+  Ok(library)
+}
+
+/// Reads the test files among `files`, loading the other files and those
+/// each test file includes for it.
+fn load_suites(engine:&Engine, matches:&ArgMatches) -> Result<Vec<(Suite, Library)>, Error> {
+  let (paths, rule_files):(Vec<_>, Vec<_>) =
+    files(matches).into_iter().partition(|file| {
+                                Path::new(file).extension().map_or(false, |extension| {
+                                                             extension == testing::EXTENSION
+                                                           })
+                              });
+  if paths.is_empty() {
+    return Err(Diagnostic::new(Kind::Other, "no test files given").into())
+  }
+  let mut suites = Vec::new();
+  for path in paths {
+    let suite = testing::read_suite(engine, Path::new(&path))?;
+    let files =
+      rule_files.iter()
+                .cloned()
+                .chain(suite.includes.iter().map(|include| include.to_string_lossy().into_owned()))
+                .collect();
+    let library = load(engine, files, matches.is_present("strict"))?;
+    suites.push((suite, library));
+  }
+  Ok(suites)
+}
+
+/// The test cases of a suite whose names contain one of the `--filter`
+/// patterns, if any are given.
+fn selected<'a>(matches:&ArgMatches, suite:&'a Suite) -> impl Iterator<Item=&'a Case> {
+  let filters = matches.values_of("filter")
+                       .map_or_else(Vec::new, |filters| filters.map(ToOwned::to_owned).collect());
+  suite.cases.iter().filter(move |case| {
+                      filters.is_empty() || filters.iter().any(|filter| case.name.contains(filter))
+                    })
+}
+
+/// Runs the test cases in the test files among `files`.
+fn test(engine:&Engine, matches:&ArgMatches, limits:&Limits, config:&Config) -> Result<(), Error> {
+  let mut verdicts = Vec::new();
+  let mut filtered = 0;
+  let mut coverage = Coverage::default();
+  for (suite, library) in load_suites(engine, matches)? {
+    coverage.add_library(engine, &library);
+    let evaluator = Evaluator::new(engine, &library);
+    let before = verdicts.len();
+    for case in selected(matches, &suite) {
+      verdicts.push(testing::run(engine, &evaluator, &suite, case, limits, config, &mut coverage));
+    }
+    filtered += suite.cases.len() - (verdicts.len() - before);
+  }
+  let format = matches.value_of("format")
+                      .and_then(testing::Format::from_name)
+                      .unwrap_or(testing::Format::Text);
+  testing::write(&verdicts, filtered, format, &mut stdout().lock()).map_err(|e| {
+    Diagnostic::new(Kind::Other, format!("could not write report: {}", e))
+  })?;
+  if let Some(format) = matches.value_of("coverage").and_then(coverage::Format::from_name) {
+    let written = match matches.value_of("coverage-file") {
+      | Some(path) =>
+        std::fs::File::create(path).and_then(|mut file| coverage.write(format, &mut file)),
+      | None => coverage.write(format, &mut stdout().lock()),
+    };
+    written.map_err(|e| Diagnostic::new(Kind::Other, format!("could not write coverage: {}", e)))?;
+  }
+  let failed = verdicts.iter().filter(|verdict| !verdict.passed).count();
+  if failed == 0 {
+    Ok(())
+  } else {
+    Err(Diagnostic::new(Kind::Test, format!("{} test(s) failed", failed)).into())
+  }
+}
+
+/// Runs the test cases in the test files among `files` against every mutant
+/// of the rules they use, reporting the mutants no test case fails for.
+fn mutate(engine:&Engine, matches:&ArgMatches, limits:&Limits, config:&Config)
+          -> Result<(), Error> {
+  let (mut total, mut survived) = (0_usize, 0_usize);
+  for (suite, library) in load_suites(engine, matches)? {
+    let cases = selected(matches, &suite).collect::<Vec<_>>();
+    let failing = |library:&Library| {
+      let evaluator = Evaluator::new(engine, library);
+      cases.iter().find(|case| {
+                    !testing::run(engine,
+                                  &evaluator,
+                                  &suite,
+                                  case,
+                                  limits,
+                                  config,
+                                  &mut Coverage::default()).passed
+                  })
+    };
+    if let Some(case) = failing(&library) {
+      return Err(Diagnostic::new(Kind::Test,
+                                 format!("test '{}' fails without any mutation", case.name))
+                 .with_path(&suite.path)
+                 .into())
+    }
+    for mutant in mutation::mutants(engine, &library.rules) {
+      let mutated = mutant.apply(&library);
+      total += 1;
+      let description = mutant.describe(engine, &library);
+      match failing(&mutated) {
+        | Some(case) =>
+          println!("{}: {} ... killed by {}", suite.path.display(), description, case.name),
+        | None => {
+          println!("{}: {} ... SURVIVED", suite.path.display(), description);
+          survived += 1;
+        },
+      }
+    }
+  }
+  println!("{} of {} mutant(s) killed, {} survived", total - survived, total, survived);
+  if survived == 0 {
+    Ok(())
+  } else {
+    Err(Diagnostic::new(Kind::Test, format!("{} mutant(s) survived", survived)).into())
+  }
+}
+
+/// Checks that two rule files rewrite generated programs to the same normal
+/// forms.
+fn equiv(engine:&Engine, matches:&ArgMatches, config:&Config) -> Result<(), Error> {
+  let paths = [required(matches, "LEFT"), required(matches, "RIGHT")];
+  let left = load(engine, vec![paths[0].to_string()], matches.is_present("strict"))?;
+  let right = load(engine, vec![paths[1].to_string()], matches.is_present("strict"))?;
+  let evaluators = [Evaluator::new(engine, &left), Evaluator::new(engine, &right)];
+  let mut rules = left.rules.clone();
+  rules.append(right.rules.clone());
+  let mut generator = Generator::new(engine, &rules, config);
+  let counterexample = property::check(&mut generator, 1, config.cases, |inputs| {
+    property::agree((&evaluators[0], &inputs[0]), (&evaluators[1], &inputs[0]), &config.limits)
+  });
+  let program = match counterexample {
+    | Some(inputs) => inputs[0].clone(),
+    | None => {
+      println!("{} and {} agree on {} generated program(s) (seed {})",
+               paths[0], paths[1], config.cases, config.seed);
+      return Ok(())
     },
-    | Err(e) => Err(format!("Error while reading '{}': {}", filename, e)),
+  };
+  let mut diagnostic = Diagnostic::new(Kind::Test,
+                                       format!("{} and {} differ on `{}`",
+                                               paths[0],
+                                               paths[1],
+                                               pretty_terms(engine, program.clone())));
+  for (path, evaluator) in paths.iter().zip(&evaluators) {
+    let evaluation = evaluator.evaluate(&program, &config.limits);
+    let result = pretty_terms(engine, evaluation.terms.clone());
+    diagnostic = diagnostic.with_note(if evaluation.outcome == Some(Outcome::Normal) {
+                                        format!("{} gives `{}`", path, result)
+                                      } else {
+                                        format!("{} gives `{}` ({})", path, result, evaluation)
+                                      });
   }
+  Err(diagnostic.with_note(format!("the seed was {}", config.seed)).into())
 }
 
-fn load_files(engine:&Engine, files:Vec<String>) -> Result<Vector<Rule>, String> {
-  let mut rules = Vector::new();
-  for file in files {
-    rules.extend(load_file(engine, &file)?);
+/// Explores every way of rewriting each program, printing the normal forms
+/// reached or the whole reduction graph.
+fn explore(engine:&Engine, matches:&ArgMatches, limits:&Limits) -> Result<(), Error> {
+  let library = load(engine, files(matches), matches.is_present("strict"))?;
+  let evaluator = Evaluator::new(engine, &library);
+  let format = matches.value_of("format")
+                      .and_then(explore::Format::from_name)
+                      .unwrap_or(explore::Format::Text);
+  let defaults = Bounds::default();
+  let bounds =
+    Bounds { depth:number(matches, "depth")?.unwrap_or(defaults.depth),
+             programs:number(matches, "max-programs")?.unwrap_or(defaults.programs),
+             size:if matches.is_present("max-size") { limits.size } else { defaults.size } };
+  each_program(programs(matches), |program| {
+    let terms = parse_program(engine, &library, program)?;
+    let graph = explore::explore(&evaluator, &terms, &bounds);
+    explore::write(engine, &evaluator, &graph, format, &mut stdout().lock()).map_err(|e| {
+      Diagnostic::new(Kind::Other, format!("could not write reduction graph: {}", e))
+    })?;
+    if format == explore::Format::Text {
+      println!("{} distinct normal form(s) among {} program(s) reached",
+               graph.normal_forms().count(),
+               graph.nodes.len());
+    }
+    if !graph.is_complete() {
+      eprintln!("{}",
+                Diagnostic::new(Kind::Limit,
+                                "exploring stopped at the bounds, so other normal forms may be \
+                                 reachable").as_warning()
+                                            .with_span(program, 0..program.len())
+                                            .with_note("raise --depth, --max-programs or \
+                                                        --max-size to explore further"));
+    }
+    Ok(())
+  })
+}
+
+/// Searches backwards from each target program for programs that rewrite to
+/// it, printing those found.
+fn invert(engine:&Engine, matches:&ArgMatches, limits:&Limits) -> Result<(), Error> {
+  let library = load(engine, files(matches), matches.is_present("strict"))?;
+  let evaluator = Evaluator::new(engine, &library).with_strategy(strategy(matches)?);
+  let defaults = inverse::Bounds::default();
+  let bounds = inverse::Bounds { depth:number(matches, "depth")?.unwrap_or(defaults.depth),
+                                 candidates:
+                                   number(matches, "max-candidates")?.unwrap_or(defaults.candidates),
+                                 programs:
+                                   number(matches, "max-programs")?.unwrap_or(defaults.programs),
+                                 size:number(matches, "max-terms")?.unwrap_or(defaults.size),
+                                 limits:Limits { steps:if matches.is_present("max-steps") {
+                                                   limits.steps
+                                                 } else {
+                                                   defaults.limits.steps
+                                                 },
+                                                 size:if matches.is_present("max-size") {
+                                                   limits.size
+                                                 } else {
+                                                   defaults.limits.size
+                                                 },
+                                                 time:limits.time } };
+  each_program(programs(matches), |program| {
+    let target = parse_program(engine, &library, program)?;
+    let search = inverse::search(engine, &evaluator, &target, &bounds);
+    for candidate in &search.candidates {
+      println!("{} ({} step(s) back, rewrites to the target in {} step(s))",
+               pretty_terms(engine, candidate.terms.clone()),
+               candidate.depth,
+               candidate.steps);
+    }
+    println!("{} candidate(s) found, {} rejected as they do not rewrite to the target",
+             search.candidates.len(),
+             search.rejected);
+    if search.cut_off {
+      eprintln!("{}",
+                Diagnostic::new(Kind::Limit,
+                                "searching stopped at the bounds, so other candidates may exist")
+                .as_warning()
+                .with_span(program, 0..program.len())
+                .with_note("raise --depth, --max-candidates, --max-programs or --max-terms to \
+                            search further"));
+    }
+    Ok(())
+  })
+}
+
+/// Replays a proof script with loaded rules, printing each step, and checks
+/// that every step applies and gives the program the script says it does.
+fn replay(engine:&Engine, matches:&ArgMatches) -> Result<(), Error> {
+  let library = load(engine, files(matches), matches.is_present("strict"))?;
+  let evaluator = Evaluator::new(engine, &library);
+  let proof = proof::read_proof(engine, Path::new(required(matches, "PROOF")))?;
+  let mut number = 0;
+  let end = proof::check(engine, &evaluator, &proof, |step| {
+    number += 1;
+    println!("{}. {} at {}: {}",
+             number,
+             evaluator.describe(step.fired),
+             step.position,
+             pretty_terms(engine, step.after.clone()));
+  })?;
+  let normal = if evaluator.next_match(&end).is_none() { ", a normal form" } else { "" };
+  println!("proof checked: `{}` rewrites to `{}` in {} step(s){}",
+           pretty_terms(engine, proof.start.clone()),
+           pretty_terms(engine, end),
+           proof.steps.len(),
+           normal);
+  Ok(())
+}
+
+/// Analyses loaded rules, reporting the rules that can loop as errors, and
+/// the rules that could not be shown to terminate and the overlapping rules
+/// whose results depend on which is used as warnings, or as errors when
+/// `strict` is set.
+fn check(engine:&Engine, matches:&ArgMatches) -> Result<(), Error> {
+  let strict = matches.is_present("strict");
+  let library = load(engine, files(matches), strict)?;
+  let evaluator = Evaluator::new(engine, &library);
+  let rule = |index:usize| {
+    pretty::rules(engine, vector![library.rules[index].clone()]).trim_end().to_string()
+  };
+  let at = |diagnostic:Diagnostic, index:usize| match library.origin(index) {
+    | Some(origin) => diagnostic.with_origin(origin),
+    | None => diagnostic,
+  };
+  let (mut shown, mut diagnostics) = (0_usize, Vec::new());
+  let terminations = termination::analyse(engine, &evaluator);
+  let total = terminations.len();
+  for (index, termination) in terminations.into_iter().enumerate() {
+    let diagnostic = match termination {
+      | Termination::Terminates(_) => {
+        shown += 1;
+        continue
+      },
+      | Termination::Loops(reason) =>
+        Diagnostic::new(Kind::Analysis, format!("rule `{}` can loop", rule(index)))
+        .with_note(reason),
+      | Termination::Unknown(reason) =>
+        Diagnostic::new(Kind::Analysis,
+                        format!("rule `{}` could not be shown to terminate", rule(index)))
+        .with_note(reason)
+        .as_warning(),
+    };
+    diagnostics.push(at(diagnostic, index));
   }
-  Ok(rules)
+  let divergences = confluence::analyse(&evaluator);
+  for divergence in &divergences {
+    let pair = &divergence.pair;
+    let diagnostic =
+      Diagnostic::new(Kind::Analysis,
+                      format!("rules `{}` and `{}` overlap on `{}`, which rewrites differently \
+                               depending on which is used",
+                              rule(pair.first),
+                              rule(pair.second),
+                              pretty_terms(engine, pair.program.clone())))
+      .as_warning()
+      .with_note(format!("using {} first gives `{}`",
+                         evaluator.describe(Fired::Rule(pair.first)),
+                         pretty_terms(engine, divergence.left.clone())))
+      .with_note(format!("using {} first gives `{}`",
+                         evaluator.describe(Fired::Rule(pair.second)),
+                         pretty_terms(engine, divergence.right.clone())));
+    diagnostics.push(at(diagnostic, pair.first));
+  }
+  let mut errors = Vec::new();
+  for diagnostic in diagnostics {
+    if strict || diagnostic.severity == Severity::Error {
+      errors.push(Diagnostic { severity:Severity::Error, ..diagnostic });
+    } else {
+      eprintln!("{}", diagnostic);
+    }
+  }
+  println!("{} of {} rule(s) shown to terminate, {} critical pair(s) that do not join",
+           shown,
+           total,
+           divergences.len());
+  if errors.is_empty() { Ok(()) } else { Err(errors.into()) }
+}
+
+/// Proposes rules that make the critical pairs of loaded rules join, and
+/// writes them to a new file for review.
+fn complete(engine:&Engine, matches:&ArgMatches) -> Result<(), Error> {
+  let library = load(engine, files(matches), matches.is_present("strict"))?;
+  let order = matches.value_of("order").and_then(Order::from_name).unwrap_or(Order::Size);
+  let defaults = completion::Limits::default();
+  let limits = completion::Limits { rounds:number(matches, "rounds")?.unwrap_or(defaults.rounds),
+                                    rules:
+                                      number(matches, "max-rules")?.unwrap_or(defaults.rules) };
+  let completion = completion::complete(engine, &library, order, &limits);
+  let output = required(matches, "output");
+  let rules = completion.rules.iter().cloned().collect::<Vector<_>>();
+  std::fs::OpenOptions::new().write(true)
+                             .create_new(true)
+                             .open(output)
+                             .and_then(|mut file| {
+                               file.write_all(pretty::rules(engine, rules).as_bytes())
+                             })
+                             .map_err(|e| {
+                               Diagnostic::new(Kind::Other,
+                                               format!("could not write proposed rules: {}", e))
+                               .with_path(Path::new(output))
+                             })?;
+  println!("proposed {} rule(s) in {} round(s), written to {}",
+           completion.rules.len(),
+           completion.rounds,
+           output);
+  if completion.remaining.is_empty() {
+    println!("the rules are locally confluent with the proposed rules added");
+    Ok(())
+  } else {
+    Err(Diagnostic::new(Kind::Analysis,
+                        format!("{} critical pair(s) still do not join",
+                                completion.remaining.len())).with_note("raise --rounds or \
+                                                                        --max-rules, or try \
+                                                                        another --order")
+                                                            .into())
+  }
+}
+
+/// The predicates a program should keep satisfying while it is minimized.
+fn predicates(engine:&Engine, library:&Library, matches:&ArgMatches)
+              -> Result<Vec<Predicate>, Error> {
+  let mut predicates = Vec::new();
+  if matches.is_present("diverges") {
+    predicates.push(Predicate::Diverges);
+  }
+  for atom in matches.values_of("contains").into_iter().flatten() {
+    let term =
+      parse::term(engine, atom).map_err(|e| format!("could not parse atom '{}': {}", atom, e))
+                               .and_then(|term| library.resolve(engine, &vector![term]))
+                               .map_err(|message| Diagnostic::new(Kind::Parse, message))?;
+    predicates.extend(term.into_iter().map(Predicate::Contains));
+  }
+  if let Some(program) = matches.value_of("result") {
+    predicates.push(Predicate::Result(parse_program(engine, library, program)?));
+  }
+  if predicates.is_empty() {
+    return Err(Diagnostic::new(Kind::Other,
+                               "expected at least one of --diverges, --contains or --result").into())
+  }
+  Ok(predicates)
 }
 
 #[tokio::main]
-async fn main() -> Result<(), String> {
-  let matches = command!().propagate_version(true)
+async fn main() {
+  if let Err(error) = cli().await {
+    eprintln!("{}", error);
+    std::process::exit(error.exit_code());
+  }
+}
+
+async fn cli() -> Result<(), Error> {
+  let matches = with_strategy(command!()).propagate_version(true)
                           .arg(arg!([FILES]).multiple_values(true).help("Rule files to use"))
+                          .arg(arg!(--plain).help("Use a line-oriented interface instead of the TUI"))
+                          .arg(arg!(--step).help("Rewrite one step at a time, waiting for a \
+                                                  keypress between steps"))
+                          .arg(arg!(--strict).global(true)
+                                             .help("Treat conflicting rules as errors"))
+                          .arg(arg!(--"max-steps" <N>).required(false)
+                                                      .global(true)
+                                                      .help("Stop rewriting after N steps (0 for \
+                                                             no limit) [default: 1000000]"))
+                          .arg(arg!(--"max-size" <N>).required(false)
+                                                     .global(true)
+                                                     .help("Stop rewriting once a program has \
+                                                            more than N terms (0 for no limit) \
+                                                            [default: 1000000]"))
+                          .arg(arg!(--timeout <MILLIS>).required(false)
+                                                       .global(true)
+                                                       .help("Stop rewriting after MILLIS \
+                                                              milliseconds"))
                           .subcommand(Command::new("edit").about("the structured editor")
-                                                          .arg(arg!([FILE]).help("Rule file to \
+                                                          .arg(arg!(<FILE>).help("Rule file to \
                                                                                   edit")))
+                          .subcommand(with_programs(Command::new("run").about("rewrite programs \
+                                                                               without the \
+                                                                               interface")))
+                          .subcommand(with_programs(Command::new("trace").about("show each step \
+                                                                                 of rewriting \
+                                                                                 programs"))
+                                      .arg(arg!(--format <FORMAT>).required(false)
+                                                                  .possible_values(["text", "json"])
+                                                                  .default_value("text")
+                                                                  .help("Output format")))
+                          .subcommand(with_inputs(Command::new("explore").about("find every \
+                                                                                 program that \
+                                                                                 programs can \
+                                                                                 be rewritten \
+                                                                                 to"))
+                                      .arg(arg!(--depth <N>).required(false)
+                                                            .help("Take at most N steps [default: 20]"))
+                                      .arg(arg!(--"max-programs" <N>).required(false)
+                                                                     .help("Reach at most N distinct programs \
+                                                                            [default: 10000]"))
+                                      .arg(arg!(--format <FORMAT>).required(false)
+                                                                  .possible_values(["text", "dot", "json"])
+                                                                  .default_value("text")
+                                                                  .help("Print the normal forms, or the \
+                                                                         whole graph as DOT or JSON")))
+                          .subcommand(with_programs(Command::new("invert").about("find programs \
+                                                                                  that rewrite \
+                                                                                  to the given \
+                                                                                  ones"))
+                                      .arg(arg!(--depth <N>).required(false)
+                                                            .help("Take at most N steps backwards [default: 4]"))
+                                      .arg(arg!(--"max-candidates" <N>).required(false)
+                                                                       .help("Stop after finding N programs \
+                                                                              [default: 20]"))
+                                      .arg(arg!(--"max-programs" <N>).required(false)
+                                                                     .help("Visit at most N distinct programs \
+                                                                            [default: 10000]"))
+                                      .arg(arg!(--"max-terms" <N>).required(false)
+                                                                  .help("Do not search further from programs \
+                                                                         with more than N terms [default: 100]")))
+                          .subcommand(with_programs(Command::new("minimize").about("shrink programs \
+                                                                                    while they keep \
+                                                                                    behaving the \
+                                                                                    same way"))
+                                      .arg(arg!(--diverges).help("Keep programs that do not terminate"))
+                                      .arg(arg!(--contains <ATOM>).required(false)
+                                                                  .multiple_occurrences(true)
+                                                                  .help("Keep programs whose normal form contains ATOM"))
+                                      .arg(arg!(--result <PROGRAM>).required(false)
+                                                                   .help("Keep programs that rewrite to PROGRAM")))
+                          .subcommand(with_properties(with_tests(Command::new("test").about("check that programs \
+                                                                             rewrite to the \
+                                                                             expected results"))
+                                                          .arg(arg!(--format <FORMAT>).required(false)
+                                                                                      .possible_values(["text", "json"])
+                                                                                      .default_value("text")
+                                                                                      .help("Report format"))
+                                                          .arg(arg!(--coverage <FORMAT>).required(false)
+                                                                                        .possible_values(["table", "lcov"])
+                                                                                        .help("Also report how often each rule fired"))
+                                                          .arg(arg!(--"coverage-file" <FILE>).required(false)
+                                                                                             .help("Write the coverage \
+                                                                                                    report to FILE \
+                                                                                                    instead of \
+                                                                                                    standard output"))))
+                          .subcommand(with_properties(with_tests(Command::new("mutate").about("check that tests \
+                                                                               fail when rules \
+                                                                               are changed"))))
+                          .subcommand(with_properties(Command::new("equiv").about("check that two \
+                                                                                   rule files \
+                                                                                   rewrite \
+                                                                                   generated \
+                                                                                   programs the \
+                                                                                   same way")
+                                                                          .arg(arg!(<LEFT>).help("Rule file"))
+                                                                          .arg(arg!(<RIGHT>).help("Rule file to compare it with"))))
+                          .subcommand(Command::new("replay").about("check a proof script \
+                                                                    saved from rewriting by \
+                                                                    hand")
+                                                            .arg(arg!(<PROOF>).help("Proof script (.mlproof)"))
+                                                            .arg(arg!([FILES]).multiple_values(true)
+                                                                              .help("Rule files the proof uses")))
+                          .subcommand(Command::new("check").about("check that rewriting with \
+                                                                   rules terminates and does \
+                                                                   not depend on their order")
+                                                           .arg(arg!([FILES]).multiple_values(true)
+                                                                             .help("Rule files to check")))
+                          .subcommand(Command::new("complete").about("propose rules that make \
+                                                                      overlapping rules agree")
+                                                              .arg(arg!([FILES]).multiple_values(true)
+                                                                                .help("Rule files to complete"))
+                                                              .arg(arg!(-o --output <FILE>).help("New rule file to write the \
+                                                                                                  proposed rules to"))
+                                                              .arg(arg!(--order <ORDER>).required(false)
+                                                                                        .possible_values(["size", "length"])
+                                                                                        .default_value("size")
+                                                                                        .help("How to decide which side \
+                                                                                               of a pair is rewritten \
+                                                                                               to the other"))
+                                                              .arg(arg!(--rounds <N>).required(false)
+                                                                                     .help("Stop after N rounds of \
+                                                                                            proposing rules \
+                                                                                            [default: 10]"))
+                                                              .arg(arg!(--"max-rules" <N>).required(false)
+                                                                                          .help("Propose at most N \
+                                                                                                 rules [default: 100]")))
+                          .subcommand(Command::new("convert").about("convert rule files between \
+                                                                     formats")
+                                                             .arg(arg!(<INPUT>).help("Rule file or \
+                                                                                      directory \
+                                                                                      to convert"))
+                                                             .arg(arg!(<OUTPUT>).help("File or \
+                                                                                       directory \
+                                                                                       to write"))
+                                                             .arg(arg!(--to <FORMAT>).required(false)
+                                                                                     .possible_values(["text", "binary", "json"])
+                                                                                     .help("Output format (otherwise \
+                                                                                            taken from the \
+                                                                                            extension)")))
                           .get_matches();
 
   let engine = Engine::new();
+  let limits = limits(matches.subcommand().map_or(&matches, |(_, sub_matches)| sub_matches))?;
 
   match matches.subcommand() {
     | Some(("edit", sub_matches)) => {
-      let filename = sub_matches.value_of("FILE").unwrap();
-      let rules = load_file(&engine, filename)?;
-      let mut path = PathBuf::from(filename);
-      let _ = path.set_extension("mlb");
-      match path.canonicalize() {
-        | Ok(path) => Editor::new(engine, path, rules)?.run().await,
-        | Err(_) => eprintln!("Path could not be canonicalized"),
+      let path = PathBuf::from(required(sub_matches, "FILE"));
+      let (contents, format, origins) = if path.exists() {
+        read_file(&engine, &path)?
+      } else {
+        (Contents::default(), Format::from_path(&path).unwrap_or(Format::Text), Vec::new())
+      };
+      Editor::new(engine, path, format, contents, origins)?.run().await;
+    },
+    | Some(("run", sub_matches)) => {
+      let library = load(&engine, files(sub_matches), sub_matches.is_present("strict"))?;
+      let evaluator = Evaluator::new(&engine, &library).with_strategy(strategy(sub_matches)?);
+      each_program(programs(sub_matches), |program| {
+        let terms = parse_program(&engine, &library, program)?;
+        let evaluation = evaluator.evaluate(&terms, &limits);
+        println!("{}", pretty_terms(&engine, evaluation.terms.clone()));
+        if evaluation.outcome == Some(Outcome::Normal) {
+          Ok(())
+        } else {
+          Err(unfinished(&evaluator, &engine, program, &evaluation))
+        }
+      })?;
+    },
+    | Some(("trace", sub_matches)) => {
+      let library = load(&engine, files(sub_matches), sub_matches.is_present("strict"))?;
+      let evaluator = Evaluator::new(&engine, &library).with_strategy(strategy(sub_matches)?);
+      let format = sub_matches.value_of("format")
+                              .and_then(trace::Format::from_name)
+                              .unwrap_or(trace::Format::Text);
+      each_program(programs(sub_matches), |program| {
+        let terms = parse_program(&engine, &library, program)?;
+        let evaluation = trace::write(&engine,
+                                      &evaluator,
+                                      &terms,
+                                      &limits,
+                                      format,
+                                      &mut stdout().lock()).map_err(|e| {
+                           Diagnostic::new(Kind::Other, format!("could not write trace: {}", e))
+                         })?;
+        if evaluation.outcome == Some(Outcome::Normal) {
+          Ok(())
+        } else {
+          Err(unfinished(&evaluator, &engine, program, &evaluation))
+        }
+      })?;
+    },
+    | Some(("minimize", sub_matches)) => {
+      let library = load(&engine, files(sub_matches), sub_matches.is_present("strict"))?;
+      let evaluator = Evaluator::new(&engine, &library).with_strategy(strategy(sub_matches)?);
+      let predicates = predicates(&engine, &library, sub_matches)?;
+      let mut holds =
+        |terms:&Vector<Term>| minimize::holds(&evaluator, &limits, &predicates, terms);
+      each_program(programs(sub_matches), |program| {
+        let terms = parse_program(&engine, &library, program)?;
+        if !holds(&terms) {
+          return Err(Diagnostic::new(Kind::Other, "the program does not behave as described to begin with")
+                     .with_span(program, 0..program.len()))
+        }
+        println!("{}", pretty_terms(&engine, minimize::minimize(&engine, terms, &mut holds)));
+        Ok(())
+      })?;
+    },
+    | Some(("invert", sub_matches)) => invert(&engine, sub_matches, &limits)?,
+    | Some(("explore", sub_matches)) => explore(&engine, sub_matches, &limits)?,
+    | Some(("test", sub_matches)) =>
+      test(&engine, sub_matches, &limits, &property_config(sub_matches, &limits)?)?,
+    | Some(("mutate", sub_matches)) =>
+      mutate(&engine, sub_matches, &limits, &property_config(sub_matches, &limits)?)?,
+    | Some(("equiv", sub_matches)) =>
+      equiv(&engine, sub_matches, &property_config(sub_matches, &limits)?)?,
+    | Some(("replay", sub_matches)) => replay(&engine, sub_matches)?,
+    | Some(("check", sub_matches)) => check(&engine, sub_matches)?,
+    | Some(("complete", sub_matches)) => complete(&engine, sub_matches)?,
+    | Some(("convert", sub_matches)) => {
+      let input = Path::new(required(sub_matches, "INPUT"));
+      let output = Path::new(required(sub_matches, "OUTPUT"));
+      let to = sub_matches.value_of("to").and_then(Format::from_name);
+      let (converted, warnings) = convert(&engine, input, output, to)?;
+      for warning in warnings {
+        eprintln!("{}", warning);
+      }
+      for (from, to) in converted {
+        println!("{} -> {}", from.display(), to.display());
       }
     },
     | _ => {
-      let mut files = Vec::new();
-      if let Some(args) = matches.values_of("FILES") {
-        files.extend(args.map(ToOwned::to_owned));
-      }
-      match load_files(&engine, files) {
-        | Ok(rules) => match Interactive::new(engine, rules) {
-          | Ok(mut interactive) => match interactive.run().await {
-            | Ok(()) => {},
-            | Err(error) => eprintln!("{}", error),
-          },
-          | Err(error) => eprintln!("{}", error),
-        },
-        | Err(error) => eprintln!("{}", error),
+      let library = load(&engine, files(&matches), matches.is_present("strict"))?;
+      let (strategy, stepping) = (strategy(&matches)?, matches.is_present("step"));
+      if matches.is_present("plain") || !stdout().is_tty() {
+        Repl::new(engine, library, limits, strategy, stepping, Repl::default_history()).run()?;
+      } else {
+        let mut interactive =
+          Interactive::new(engine, library, limits, strategy, stepping).map_err(|e| e.to_string())?;
+        interactive.run().await.map_err(|e| e.to_string())?;
       }
     },
   }
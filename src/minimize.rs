@@ -0,0 +1,146 @@
+//! Shrinking a program to a minimal one that still behaves the same way,
+//! by delta debugging.
+//!
+//! The top-level terms are removed a chunk at a time, first in large chunks
+//! and then in smaller ones, after which each quote is unwrapped if it can be
+//! and the terms inside it are shrunk in the same way. This is repeated until
+//! nothing more can be removed.
+
+use im::Vector;
+use mlatu_lib::{Engine, Term};
+
+use crate::eval::{Evaluator, Limits, Outcome};
+
+/// Something a program should keep doing while it is shrunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Predicate {
+  /// The program does not reach a normal form within the limits
+  Diverges,
+  /// The normal form contains an atom, at the top level or inside a quote
+  Contains(Term),
+  /// The normal form is exactly this
+  Result(Vector<Term>),
+}
+
+fn contains(terms:&Vector<Term>, atom:&Term) -> bool {
+  terms.iter().any(|term| match term {
+                | Term::Quote(terms) => contains(terms, atom),
+                | _ => term == atom,
+              })
+}
+
+/// Whether every predicate holds for a program.
+#[must_use]
+pub fn holds(evaluator:&Evaluator<'_>, limits:&Limits, predicates:&[Predicate],
+             terms:&Vector<Term>)
+             -> bool {
+  let evaluation = evaluator.evaluate(terms, limits);
+  let normal = evaluation.outcome == Some(Outcome::Normal);
+  predicates.iter().all(|predicate| match predicate {
+                     | Predicate::Diverges => !normal,
+                     | Predicate::Contains(atom) => normal && contains(&evaluation.terms, atom),
+                     | Predicate::Result(expected) => normal && evaluation.terms == *expected,
+                   })
+}
+
+/// Removes chunks of terms while `holds` still holds, halving the size of
+/// the chunks whenever none of them can be removed.
+fn remove_chunks(terms:Vector<Term>, holds:&mut dyn FnMut(&Vector<Term>) -> bool) -> Vector<Term> {
+  let mut terms = terms;
+  let mut chunks = 2;
+  while terms.len() >= 2 {
+    let size = (terms.len() + chunks - 1) / chunks;
+    let mut reduced = false;
+    let mut start = 0;
+    while start < terms.len() {
+      let end = (start + size).min(terms.len());
+      let mut complement = terms.clone();
+      let after = complement.split_off(start).skip(end - start);
+      complement.append(after);
+      if holds(&complement) {
+        terms = complement;
+        reduced = true;
+      } else {
+        start = end;
+      }
+    }
+    if reduced {
+      chunks = chunks.saturating_sub(1).max(2);
+    } else if size == 1 {
+      break
+    } else {
+      chunks = (chunks * 2).min(terms.len());
+    }
+  }
+  if terms.len() == 1 && holds(&Vector::new()) {
+    terms = Vector::new();
+  }
+  terms
+}
+
+/// Shrinks a program while `holds` still holds for it. `holds` should hold
+/// for the program it is given.
+pub fn minimize(engine:&Engine, terms:Vector<Term>, holds:&mut dyn FnMut(&Vector<Term>) -> bool)
+                -> Vector<Term> {
+  let mut terms = terms;
+  loop {
+    let before = terms.clone();
+    terms = remove_chunks(terms, holds);
+    let mut i = 0;
+    while i < terms.len() {
+      if let Term::Quote(quoted) = terms[i].clone() {
+        let mut unwrapped = terms.clone();
+        let after = unwrapped.split_off(i).skip(1);
+        unwrapped.append(quoted.clone());
+        unwrapped.append(after);
+        if holds(&unwrapped) {
+          terms = unwrapped;
+          continue
+        }
+        let quoted = minimize(engine, quoted, &mut |quoted| {
+          let mut inside = terms.clone();
+          inside[i] = Term::make_quote(engine, quoted.clone()).clone();
+          holds(&inside)
+        });
+        terms[i] = Term::make_quote(engine, quoted).clone();
+      }
+      i += 1;
+    }
+    if terms == before {
+      return terms
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use mlatu_lib::{parse, Engine};
+
+  use super::{contains, holds, minimize, Predicate};
+  use crate::eval::{Evaluator, Limits};
+  use crate::library::tests::parsed;
+
+  #[test]
+  fn keeps_only_what_the_predicate_needs() {
+    let engine = Engine::new();
+    let program = |text:&str| parse::terms(&engine, text).expect("the program parses");
+    let bad = parse::term(&engine, "bad").expect("the atom parses");
+    let shrunk =
+      minimize(&engine, program("a (b (c bad) d) e f"), &mut |terms| contains(terms, &bad));
+    assert_eq!(shrunk, program("bad"));
+  }
+
+  #[test]
+  fn keeps_a_diverging_program_diverging() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "loop = loop. a = b.");
+    let evaluator = Evaluator::new(&engine, &library);
+    let limits = Limits { steps:Some(100), ..Limits::default() };
+    let predicates = [Predicate::Diverges];
+    let terms = parse::terms(&engine, "a (a) loop a").expect("the program parses");
+    assert!(holds(&evaluator, &limits, &predicates, &terms));
+    let shrunk =
+      minimize(&engine, terms, &mut |terms| holds(&evaluator, &limits, &predicates, terms));
+    assert_eq!(shrunk, parse::terms(&engine, "loop").expect("the program parses"));
+  }
+}
@@ -0,0 +1,137 @@
+//! Altering loaded rules one at a time, to judge how thoroughly tests check
+//! them: a mutant that every test still passes with is a change the tests
+//! cannot tell apart from the original rules.
+
+use im::Vector;
+use mlatu_lib::{pretty, Engine, Rule, Term};
+
+use crate::eval::{primitives, Primitive};
+use crate::Library;
+
+/// A change to one rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mutation {
+  /// The rule is removed
+  Delete,
+  /// The term at a position of the reduction is removed
+  Drop(usize),
+  /// The terms at a position of the reduction and the one after it are
+  /// swapped
+  Swap(usize),
+  /// The primitive at a position of the reduction is replaced by another
+  Replace(usize, Primitive),
+}
+
+/// The loaded rules with one of them changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mutant {
+  /// The index of the changed rule
+  pub rule:usize,
+  pub mutation:Mutation,
+  /// The changed rule, or `None` if it is deleted
+  pub changed:Option<Rule>,
+}
+
+impl Mutant {
+  /// The library the mutant makes of the one it was made from, whose rules
+  /// and their origins are changed together.
+  #[must_use]
+  pub fn apply(&self, library:&Library) -> Library {
+    library.with_rule(self.rule, self.changed.clone())
+  }
+
+  /// A description of the change, e.g. ``replaced `+` with `-` in rule 2 of
+  /// nat.mlt:4``.
+  #[must_use]
+  pub fn describe(&self, engine:&Engine, library:&Library) -> String {
+    let rule = library.origin(self.rule)
+                      .map_or_else(|| format!("rule {}", self.rule + 1), ToString::to_string);
+    let reduction = &library.rules[self.rule].reduction;
+    let term = |position:usize| {
+      reduction.get(position).map_or_else(String::new, |term| pretty::term(engine, term.clone()))
+    };
+    match &self.mutation {
+      | Mutation::Delete => format!("deleted {}", rule),
+      | Mutation::Drop(position) => format!("dropped `{}` from {}", term(*position), rule),
+      | Mutation::Swap(position) =>
+        format!("swapped `{}` and `{}` in {}", term(*position), term(position + 1), rule),
+      | Mutation::Replace(position, primitive) =>
+        format!("replaced `{}` with `{}` in {}", term(*position), primitive.symbol(), rule),
+    }
+  }
+}
+
+/// Every mutant of some rules, rule by rule.
+#[must_use]
+pub fn mutants(engine:&Engine, rules:&Vector<Rule>) -> Vec<Mutant> {
+  let primitives = primitives(engine);
+  let mut mutants = Vec::new();
+  for (index, rule) in rules.iter().enumerate() {
+    if rule.redex.is_empty() {
+      continue
+    }
+    let mut mutant = |mutation:Mutation, changed:Option<Rule>| {
+      mutants.push(Mutant { rule:index, mutation, changed });
+    };
+    mutant(Mutation::Delete, None);
+    let with_reduction =
+      |reduction:Vector<Term>| Some(Rule { redex:rule.redex.clone(), reduction });
+    for position in 0..rule.reduction.len() {
+      let mut reduction = rule.reduction.clone();
+      let term = reduction.remove(position);
+      mutant(Mutation::Drop(position), with_reduction(reduction));
+      if rule.reduction.get(position + 1).map_or(false, |next| *next != term) {
+        let mut reduction = rule.reduction.clone();
+        reduction.swap(position, position + 1);
+        mutant(Mutation::Swap(position), with_reduction(reduction));
+      }
+      if primitives.iter().any(|(primitive, _)| *primitive == term) {
+        for (replacement, primitive) in primitives.iter().filter(|(other, _)| *other != term) {
+          let mut reduction = rule.reduction.clone();
+          reduction[position] = replacement.clone();
+          mutant(Mutation::Replace(position, *primitive), with_reduction(reduction));
+        }
+      }
+    }
+  }
+  mutants
+}
+
+#[cfg(test)]
+mod tests {
+  use mlatu_lib::Engine;
+
+  use super::{mutants, Mutation};
+  use crate::eval::Primitive;
+  use crate::library::tests::parsed;
+
+  #[test]
+  fn mutates_each_rule_in_turn() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = x +. b = y.");
+    let mutations = mutants(&engine, &library.rules).into_iter()
+                                                    .map(|mutant| (mutant.rule, mutant.mutation))
+                                                    .collect::<Vec<_>>();
+    let mut expected = vec![(0, Mutation::Delete),
+                            (0, Mutation::Drop(0)),
+                            (0, Mutation::Swap(0)),
+                            (0, Mutation::Drop(1))];
+    expected.extend(Primitive::ALL.iter()
+                                  .filter(|primitive| **primitive != Primitive::Copy)
+                                  .map(|primitive| (0, Mutation::Replace(1, *primitive))));
+    expected.extend([(1, Mutation::Delete), (1, Mutation::Drop(0))]);
+    assert_eq!(mutations, expected);
+  }
+
+  #[test]
+  fn describes_the_change_against_the_original_rules() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = x +. b = y.");
+    let mutants = mutants(&engine, &library.rules);
+    let deleted = &mutants[0];
+    assert_eq!(deleted.describe(&engine, &library), "deleted rule 1 of rules.mlt");
+    assert_eq!(deleted.apply(&library).rules, library.rules.skip(1));
+    assert_eq!(mutants[2].describe(&engine, &library),
+               "swapped `x` and `+` in rule 1 of rules.mlt");
+  }
+}
@@ -0,0 +1,259 @@
+//! Proof scripts, which record derivations made by applying rules by hand so
+//! that they can be replayed and checked later, for `mlatu replay`.
+//!
+//! A proof script (`.mlproof`) starts with a line `program: terms` giving the
+//! program the derivation starts from, followed by a line for each step,
+//! written `position: rule => program`. The rule is written as in a text rule
+//! file, e.g. `not true = false.`, or is the symbol of a primitive, and the
+//! program is the result of the step. Blank lines and lines starting with `#`
+//! are ignored.
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use im::{vector, Vector};
+use mlatu_lib::{parse, pretty, Engine, Rule, Term};
+
+use crate::diagnostic::{Diagnostic, Error, Kind};
+use crate::eval::{Evaluator, Fired, Primitive, Step};
+use crate::pretty_terms;
+
+/// The extension of proof scripts.
+pub const EXTENSION:&str = "mlproof";
+
+/// What a step of a proof applies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum By {
+  /// A rule, pretty-printed
+  Rule(String),
+  Primitive(Primitive),
+}
+
+/// One step of a proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofStep {
+  /// The byte range of the step in the script
+  pub bytes:Range<usize>,
+  /// The index of the first term that is rewritten
+  pub position:usize,
+  pub by:By,
+  /// The program after the step
+  pub after:Vector<Term>,
+}
+
+/// A derivation from a program, step by step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proof {
+  pub path:PathBuf,
+  pub source:String,
+  pub start:Vector<Term>,
+  pub steps:Vec<ProofStep>,
+}
+
+/// A rule as it is written in proof scripts.
+#[must_use]
+pub fn rule_text(engine:&Engine, rule:&Rule) -> String {
+  pretty::rules(engine, vector![rule.clone()]).trim_end().to_string()
+}
+
+/// What a step applied, as it is written in proof scripts.
+fn by_text(engine:&Engine, evaluator:&Evaluator<'_>, fired:Fired) -> String {
+  match fired {
+    | Fired::Rule(index) => rule_text(engine, &evaluator.library().rules[index]),
+    | Fired::Primitive(primitive) => primitive.symbol().to_string(),
+  }
+}
+
+/// Writes a derivation from a program as a proof script.
+#[must_use]
+pub fn write(engine:&Engine, evaluator:&Evaluator<'_>, start:&Vector<Term>, steps:&[Step])
+             -> String {
+  let mut script = format!("program: {}\n", pretty_terms(engine, start.clone()));
+  for step in steps {
+    script.push_str(&format!("{}: {} => {}\n",
+                             step.position,
+                             by_text(engine, evaluator, step.fired),
+                             pretty_terms(engine, step.after.clone())));
+  }
+  script
+}
+
+/// Parses one step of a proof script, given the byte offset of its line.
+fn step(engine:&Engine, source:&str, line:&str, offset:usize) -> Result<ProofStep, Diagnostic> {
+  let bytes = offset..offset + line.trim_end().len();
+  let error =
+    |message:String| Diagnostic::new(Kind::Parse, message).with_span(source, bytes.clone());
+  let (position, rest) =
+    line.split_once(':').ok_or_else(|| error("expected `position: rule => program`".into()))?;
+  let position = position.trim()
+                         .parse()
+                         .map_err(|_| error(format!("invalid position '{}'", position.trim())))?;
+  let (by, after) =
+    rest.rsplit_once("=>").ok_or_else(|| error("expected `=>` before the result".into()))?;
+  let by = match Primitive::ALL.iter().find(|primitive| primitive.symbol() == by.trim()) {
+    | Some(primitive) => By::Primitive(*primitive),
+    | None => match parse::rules(engine, by.trim()) {
+      | Ok(rules) if rules.len() == 1 => By::Rule(rule_text(engine, &rules[0])),
+      | Ok(_) => return Err(error("expected a single rule or a primitive".into())),
+      | Err(e) => return Err(error(format!("could not parse rule: {}", e))),
+    },
+  };
+  let after = parse::terms(engine, after.trim()).map_err(|e| {
+                                                  error(format!("could not parse program: {}", e))
+                                                })?;
+  Ok(ProofStep { bytes, position, by, after })
+}
+
+/// Parses the source text of a proof script.
+///
+/// # Errors
+///
+/// Returns `Err` with a diagnostic for every line that could not be parsed
+pub fn parse_proof(engine:&Engine, path:&Path, source:&str) -> Result<Proof, Error> {
+  let mut start = None;
+  let mut steps = Vec::new();
+  let mut diagnostics = Vec::new();
+  let mut offset = 0;
+  for line in source.split_inclusive('\n') {
+    let trimmed = line.trim();
+    if !trimmed.is_empty() && !trimmed.starts_with('#') {
+      let bytes = offset..offset + line.trim_end().len();
+      let parsed = if start.is_some() {
+        step(engine, source, line, offset).map(|step| steps.push(step))
+      } else if let Some(program) = trimmed.strip_prefix("program:") {
+        parse::terms(engine, program).map(|terms| start = Some(terms)).map_err(|e| {
+          Diagnostic::new(Kind::Parse, format!("could not parse program: {}", e)).with_span(source,
+                                                                                           bytes)
+        })
+      } else {
+        Err(Diagnostic::new(Kind::Parse, "expected `program:` before the first step")
+            .with_span(source, bytes))
+      };
+      if let Err(diagnostic) = parsed {
+        diagnostics.push(diagnostic.with_path(path));
+      }
+    }
+    offset += line.len();
+  }
+  match start {
+    | Some(start) if diagnostics.is_empty() =>
+      Ok(Proof { path:path.to_path_buf(), source:source.to_string(), start, steps }),
+    | None if diagnostics.is_empty() =>
+      Err(Diagnostic::new(Kind::Parse, "the proof script has no `program:` line").with_path(path)
+                                                                                 .into()),
+    | _ => Err(diagnostics.into()),
+  }
+}
+
+/// Reads and parses a proof script.
+///
+/// # Errors
+///
+/// Returns `Err` if the file could not be read or parsed
+pub fn read_proof(engine:&Engine, path:&Path) -> Result<Proof, Error> {
+  let source = std::fs::read_to_string(path).map_err(|e| {
+                 Diagnostic::new(Kind::Read, format!("could not read file: {}", e)).with_path(path)
+               })?;
+  parse_proof(engine, path, &source)
+}
+
+/// Replays a proof with the rules of an evaluator, calling `replayed` with
+/// each step, and returns the program it ends with.
+///
+/// # Errors
+///
+/// Returns `Err` at the first step that does not apply or does not give the
+/// program the proof says it does
+pub fn check(engine:&Engine, evaluator:&Evaluator<'_>, proof:&Proof,
+             mut replayed:impl FnMut(&Step))
+             -> Result<Vector<Term>, Diagnostic> {
+  let library = evaluator.library();
+  let mut current =
+    library.resolve(engine, &proof.start)
+           .map_err(|message| Diagnostic::new(Kind::Parse, message).with_path(&proof.path))?;
+  for step in &proof.steps {
+    let error = |message:String| {
+      Diagnostic::new(Kind::Test, message).with_path(&proof.path)
+                                          .with_span(&proof.source, step.bytes.clone())
+    };
+    let applies = |fired:Fired| match (&step.by, fired) {
+      | (By::Rule(text), Fired::Rule(index)) => rule_text(engine, &library.rules[index]) == *text,
+      | (By::Primitive(primitive), Fired::Primitive(fired)) => *primitive == fired,
+      | _ => false,
+    };
+    let found = evaluator.matches(&current)
+                         .into_iter()
+                         .find(|found| found.position == step.position && applies(found.fired));
+    let found = found.ok_or_else(|| {
+                       error(format!("this step does not apply at position {} of `{}`",
+                                     step.position,
+                                     pretty_terms(engine, current.clone())))
+                     })?;
+    let after = evaluator.apply(&current, &found);
+    // Saved proofs show private atoms qualified, which resolving rejects
+    if after != step.after && library.resolve(engine, &step.after).as_ref() != Ok(&after) {
+      return Err(error(format!("this step gives `{}`, not `{}`",
+                               pretty_terms(engine, after),
+                               pretty_terms(engine, step.after.clone()))))
+    }
+    replayed(&Step { fired:found.fired,
+                     position:found.position,
+                     before:current,
+                     after:after.clone() });
+    current = after;
+  }
+  Ok(current)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use mlatu_lib::{parse, Engine};
+
+  use super::{check, parse_proof, write};
+  use crate::eval::Evaluator;
+  use crate::library::tests::parsed;
+  use crate::pretty_terms;
+
+  #[test]
+  fn replays_a_written_derivation() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = x. (b) (b) = z.");
+    let evaluator = Evaluator::new(&engine, &library);
+    let start = parse::terms(&engine, "(b) + a").expect("the program parses");
+    let steps = evaluator.trace(&start).collect::<Vec<_>>();
+    let script = write(&engine, &evaluator, &start, &steps);
+    let proof = parse_proof(&engine, Path::new("p.mlproof"), &script).expect("the script parses");
+    let mut replayed = Vec::new();
+    let end = check(&engine, &evaluator, &proof, |step| replayed.push(step.clone()))
+              .expect("the proof checks");
+    assert_eq!(replayed, steps);
+    assert_eq!(pretty_terms(&engine, end), "z x");
+  }
+
+  #[test]
+  fn reports_the_first_step_that_does_not_hold() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = x. b = y.");
+    let evaluator = Evaluator::new(&engine, &library);
+    let message = |script:&str| {
+      let proof = parse_proof(&engine, Path::new("p.mlproof"), script).expect("the script parses");
+      check(&engine, &evaluator, &proof, |_| {}).expect_err("the proof fails").message
+    };
+    assert_eq!(message("program: a b\n0: b = y. => a y\n"),
+               "this step does not apply at position 0 of `a b`");
+    assert_eq!(message("program: a b\n0: a = x. => y b\n1: b = y. => x y\n"),
+               "this step gives `x b`, not `y b`");
+  }
+
+  #[test]
+  fn reports_malformed_scripts() {
+    let engine = Engine::new();
+    let error = parse_proof(&engine, Path::new("p.mlproof"), "0: a = x. => x\nprogram: a\nx\n")
+                .expect_err("the script is malformed");
+    let messages = error.diagnostics.iter().map(|diagnostic| diagnostic.message.as_str());
+    assert_eq!(messages.collect::<Vec<_>>(), vec!["expected `program:` before the first step",
+                                                  "expected `position: rule => program`"]);
+  }
+}
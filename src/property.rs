@@ -0,0 +1,282 @@
+//! Checking properties of programs with randomly generated inputs.
+//!
+//! A [`Generator`] builds random programs from the atoms of some rules and
+//! the primitives, with quotes nested up to a maximum depth. When a property
+//! does not hold for a generated input, the input is shrunk by removing terms
+//! and unquoting quotes for as long as the property still does not hold, so
+//! that the counterexample reported is as small as possible.
+
+use im::Vector;
+use mlatu_lib::{pretty, Engine, Rule, Term};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::eval::{primitives, Evaluation, Evaluator, Limits, Outcome};
+
+/// The first character of the atoms that stand for generated programs in
+/// properties, e.g. `$x`.
+pub const VARIABLE:char = '$';
+
+/// How properties are checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+  /// The number of inputs to check a property with
+  pub cases:usize,
+  /// How deeply generated quotes can be nested
+  pub depth:usize,
+  /// The most terms in a generated sequence, at the top level or in a quote
+  pub length:usize,
+  /// The seed of the random inputs, so that a failure can be reproduced
+  pub seed:u64,
+  /// The limits on rewriting generated programs, which are lower than the
+  /// usual ones as many generated programs do not terminate
+  pub limits:Limits,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Self { cases:100,
+           depth:2,
+           length:4,
+           seed:rand::random(),
+           limits:Limits { steps:Some(10_000), size:Some(10_000), time:None } }
+  }
+}
+
+/// Whether a term is a variable of a property.
+fn is_variable(engine:&Engine, term:&Term) -> bool {
+  match term {
+    | Term::Quote(_) => false,
+    | _ => pretty::term(engine, term.clone()).starts_with(VARIABLE),
+  }
+}
+
+/// Adds the atoms in some terms that are not yet in `found`, including those
+/// inside quotes but not variables.
+fn atoms(engine:&Engine, terms:&Vector<Term>, found:&mut Vec<Term>) {
+  for term in terms {
+    match term {
+      | Term::Quote(terms) => atoms(engine, terms, found),
+      | _ =>
+        if !found.contains(term) && !is_variable(engine, term) {
+          found.push(term.clone());
+        },
+    }
+  }
+}
+
+/// Generates random programs.
+pub struct Generator<'a> {
+  engine:&'a Engine,
+  /// The atoms programs are made of
+  atoms:Vec<Term>,
+  depth:usize,
+  length:usize,
+  rng:StdRng,
+}
+
+impl<'a> Generator<'a> {
+  /// A generator of programs made of the atoms of some rules and the
+  /// primitives.
+  #[must_use]
+  pub fn new(engine:&'a Engine, rules:&Vector<Rule>, config:&Config) -> Self {
+    let mut found = Vec::new();
+    for rule in rules {
+      atoms(engine, &rule.redex, &mut found);
+      atoms(engine, &rule.reduction, &mut found);
+    }
+    for (term, _) in primitives(engine) {
+      atoms(engine, &Vector::unit(term), &mut found);
+    }
+    Self { engine,
+           atoms:found,
+           depth:config.depth,
+           length:config.length,
+           rng:StdRng::seed_from_u64(config.seed) }
+  }
+
+  /// A random program.
+  pub fn program(&mut self) -> Vector<Term> { self.sequence(self.depth) }
+
+  fn sequence(&mut self, depth:usize) -> Vector<Term> {
+    let mut terms = Vector::new();
+    for _ in 0..self.rng.gen_range(0..=self.length) {
+      if self.atoms.is_empty() || (depth > 0 && self.rng.gen_bool(0.25)) {
+        let quoted = self.sequence(depth.saturating_sub(1));
+        terms.push_back(Term::make_quote(self.engine, quoted).clone());
+      } else {
+        terms.push_back(self.atoms[self.rng.gen_range(0..self.atoms.len())].clone());
+      }
+    }
+    terms
+  }
+}
+
+/// The variables in some programs, each once in the order they first appear.
+#[must_use]
+pub fn variables(engine:&Engine, programs:&[&Vector<Term>]) -> Vec<Term> {
+  fn find(engine:&Engine, terms:&Vector<Term>, found:&mut Vec<Term>) {
+    for term in terms {
+      match term {
+        | Term::Quote(terms) => find(engine, terms, found),
+        | _ =>
+          if is_variable(engine, term) && !found.contains(term) {
+            found.push(term.clone());
+          },
+      }
+    }
+  }
+  let mut found = Vec::new();
+  for program in programs {
+    find(engine, program, &mut found);
+  }
+  found
+}
+
+/// Replaces each variable in a program with the terms it is bound to,
+/// including inside quotes.
+#[must_use]
+pub fn substitute(engine:&Engine, terms:&Vector<Term>, bindings:&[(Term, Vector<Term>)])
+                  -> Vector<Term> {
+  let mut substituted = Vector::new();
+  for term in terms {
+    match term {
+      | Term::Quote(terms) => {
+        let terms = substitute(engine, terms, bindings);
+        substituted.push_back(Term::make_quote(engine, terms).clone());
+      },
+      | _ => match bindings.iter().find(|(variable, _)| variable == term) {
+        | Some((_, bound)) => substituted.append(bound.clone()),
+        | None => substituted.push_back(term.clone()),
+      },
+    }
+  }
+  substituted
+}
+
+/// Whether two programs rewrite to the same normal form, each with the rules
+/// of its own evaluator.
+#[must_use]
+pub fn agree(left:(&Evaluator<'_>, &Vector<Term>), right:(&Evaluator<'_>, &Vector<Term>),
+             limits:&Limits)
+             -> bool {
+  same_result(&left.0.evaluate(left.1, limits), &right.0.evaluate(right.1, limits))
+}
+
+/// Whether two evaluations reached the same normal form.
+///
+/// Two evaluations that both failed to reach a normal form within the limits
+/// are counted as agreeing, since nothing more is known about them.
+#[must_use]
+pub fn same_result(left:&Evaluation, right:&Evaluation) -> bool {
+  match (left.outcome == Some(Outcome::Normal), right.outcome == Some(Outcome::Normal)) {
+    | (true, true) => left.terms == right.terms,
+    | (false, false) => true,
+    | _ => false,
+  }
+}
+
+/// Programs one step smaller than a program: with a term removed, with a
+/// quote replaced by its contents, or with a quote replaced by a smaller one.
+fn smaller(engine:&Engine, terms:&Vector<Term>) -> Vec<Vector<Term>> {
+  let mut candidates = Vec::new();
+  for (i, term) in terms.iter().enumerate() {
+    let mut removed = terms.clone();
+    let _term = removed.remove(i);
+    candidates.push(removed);
+    if let Term::Quote(quoted) = term {
+      let mut unquoted = terms.clone();
+      let after = unquoted.split_off(i).skip(1);
+      unquoted.append(quoted.clone());
+      unquoted.append(after);
+      candidates.push(unquoted);
+      for quoted in smaller(engine, quoted) {
+        let mut shrunk = terms.clone();
+        shrunk[i] = Term::make_quote(engine, quoted).clone();
+        candidates.push(shrunk);
+      }
+    }
+  }
+  candidates
+}
+
+/// Shrinks the inputs a property does not hold for, for as long as it still
+/// does not hold.
+pub fn shrink(engine:&Engine, mut inputs:Vec<Vector<Term>>,
+              holds:&mut impl FnMut(&[Vector<Term>]) -> bool)
+              -> Vec<Vector<Term>> {
+  'shrinking: loop {
+    for i in 0..inputs.len() {
+      for candidate in smaller(engine, &inputs[i]) {
+        let mut trial = inputs.clone();
+        trial[i] = candidate;
+        if !holds(&trial) {
+          inputs = trial;
+          continue 'shrinking
+        }
+      }
+    }
+    return inputs
+  }
+}
+
+/// Checks a property of some number of generated inputs, returning the
+/// shrunk inputs of the first counterexample found, if any.
+pub fn check(generator:&mut Generator<'_>, inputs:usize, cases:usize,
+             mut holds:impl FnMut(&[Vector<Term>]) -> bool)
+             -> Option<Vec<Vector<Term>>> {
+  // A property without inputs gives the same result every time
+  let cases = if inputs == 0 { 1 } else { cases };
+  for _ in 0..cases {
+    let generated = (0..inputs).map(|_| generator.program()).collect::<Vec<_>>();
+    if !holds(&generated) {
+      return Some(shrink(generator.engine, generated, &mut holds))
+    }
+  }
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use mlatu_lib::{parse, Engine};
+
+  use super::{check, substitute, variables, Config, Generator};
+  use crate::library::tests::parsed;
+
+  #[test]
+  fn substitutes_variables_inside_quotes() {
+    let engine = Engine::new();
+    let program = |text:&str| parse::terms(&engine, text).expect("the program parses");
+    let terms = program("$x ($y $x) a");
+    let found = variables(&engine, &[&terms]);
+    assert_eq!(found, program("$x $y").into_iter().collect::<Vec<_>>());
+    let bindings = vec![(found[0].clone(), program("b c")), (found[1].clone(), program(""))];
+    assert_eq!(substitute(&engine, &terms, &bindings), program("b c (b c) a"));
+  }
+
+  #[test]
+  fn generates_the_same_programs_from_a_seed() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = b.");
+    let config = Config { seed:7, ..Config::default() };
+    let mut first = Generator::new(&engine, &library.rules, &config);
+    let mut second = Generator::new(&engine, &library.rules, &config);
+    for _ in 0..20 {
+      let program = first.program();
+      assert!(program.len() <= config.length);
+      assert_eq!(program, second.program());
+    }
+  }
+
+  #[test]
+  fn shrinks_the_counterexample_found() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = b.");
+    let a = parse::term(&engine, "a").expect("the atom parses");
+    let mut generator =
+      Generator::new(&engine, &library.rules, &Config { seed:3, ..Config::default() });
+    let counterexample =
+      check(&mut generator, 1, 1000, |inputs| !inputs[0].contains(&a)).expect("one is found");
+    assert_eq!(counterexample, vec![parse::terms(&engine, "a").expect("the program parses")]);
+  }
+}
@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+
+use im::Vector;
+use mlatu_lib::{parse, Engine, Term};
+use rustyline::error::ReadlineError;
+
+use crate::eval::{Evaluation, Evaluator, Limits, Outcome, Strategy};
+use crate::{pretty_terms, Library};
+
+/// A line-oriented alternative to [`crate::Interactive`] that works without a
+/// terminal, e.g. over pipes, in dumb terminals or in CI.
+pub struct Repl {
+  library:Library,
+  engine:Engine,
+  limits:Limits,
+  strategy:Strategy,
+  /// Whether rewriting waits for input before each step
+  stepping:bool,
+  editor:rustyline::Editor<()>,
+  history:Option<PathBuf>,
+}
+
+/// Rewrites a program one step at a time, printing each step and waiting
+/// for a line of input before the next: an empty line takes another step,
+/// `c` continues to the end and `q` stops.
+fn step_through(editor:&mut rustyline::Editor<()>, engine:&Engine, evaluator:&Evaluator<'_>,
+                terms:&Vector<Term>, limits:&Limits)
+                -> Result<Evaluation, ReadlineError> {
+  let mut evaluation = evaluator.start(terms);
+  println!("{}", pretty_terms(engine, evaluation.terms.clone()));
+  while let Some(step) = evaluator.next_step(&mut evaluation, limits) {
+    println!("{}. {} at {}: {}",
+             evaluation.steps,
+             evaluator.describe(step.fired),
+             step.position,
+             pretty_terms(engine, step.after));
+    if evaluation.is_finished() || evaluator.next_match(&evaluation.terms).is_none() {
+      continue
+    }
+    match editor.readline("step> ") {
+      | Ok(line) if line.trim() == "c" => {
+        while evaluator.next_step(&mut evaluation, limits).is_some() {}
+      },
+      | Ok(line) if line.trim() == "q" => evaluation.cancel(),
+      | Ok(_) => {},
+      | Err(ReadlineError::Interrupted | ReadlineError::Eof) => evaluation.cancel(),
+      | Err(e) => return Err(e),
+    }
+  }
+  Ok(evaluation)
+}
+
+impl Repl {
+  /// Creates a REPL that rewrites within some limits with a strategy, one
+  /// step at a time if `stepping` is set, loading previous input from the
+  /// history file if there is one.
+  #[must_use]
+  pub fn new(engine:Engine, library:Library, limits:Limits, strategy:Strategy, stepping:bool,
+             history:Option<PathBuf>)
+             -> Self {
+    let mut editor = rustyline::Editor::<()>::new();
+    if let Some(path) = &history {
+      // A missing history file just means this is the first session
+      let _result = editor.load_history(path);
+    }
+    Self { library, engine, limits, strategy, stepping, editor, history }
+  }
+
+  /// The default history file, `.mlatu_history` in the home directory.
+  #[must_use]
+  pub fn default_history() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".mlatu_history"))
+  }
+
+  /// Carries out a command: `:strategy NAME [SEED]` to change the strategy
+  /// or `:step` to turn stepping on or off.
+  fn command(&mut self, command:&str) {
+    let words = command.split_whitespace().collect::<Vec<_>>();
+    match words.as_slice() {
+      | [":step"] => {
+        self.stepping = !self.stepping;
+        println!("stepping {}", if self.stepping { "on" } else { "off" });
+      },
+      | [":strategy", name, seed @ ..] => {
+        let seed = match seed {
+          | [] => Ok(rand::random()),
+          | [seed] => seed.parse().map_err(|_| format!("invalid seed '{}'", seed)),
+          | _ => Err("expected at most one seed".to_string()),
+        };
+        match seed.and_then(|seed| {
+                    Strategy::from_name(name, seed).ok_or_else(|| {
+                                                     format!("unknown strategy '{}', expected one \
+                                                              of {}",
+                                                             name,
+                                                             Strategy::NAMES.join(", "))
+                                                   })
+                  }) {
+          | Ok(strategy) => {
+            self.strategy = strategy;
+            println!("using the {} strategy", strategy);
+          },
+          | Err(e) => println!("{}", e),
+        }
+      },
+      | _ => println!("unknown command, expected :step or :strategy NAME [SEED]"),
+    }
+  }
+
+  /// # Errors
+  ///
+  /// Returns `Err` if there was an IO error reading input or writing the
+  /// history file
+  pub fn run(&mut self) -> Result<(), String> {
+    loop {
+      match self.editor.readline("> ") {
+        | Ok(line) => {
+          if line.trim().is_empty() {
+            continue
+          }
+          let _added = self.editor.add_history_entry(line.as_str());
+          if line.trim_start().starts_with(':') {
+            self.command(&line);
+            continue
+          }
+          let parsed =
+            parse::terms(&self.engine, &line).map_err(|e| format!("Error while parsing: {}", e));
+          match parsed.and_then(|terms| {
+                        self.library
+                            .resolve(&self.engine, &terms)
+                            .map_err(|message| format!("Error: {}", message))
+                      }) {
+            | Ok(terms) => {
+              let evaluator =
+                Evaluator::new(&self.engine, &self.library).with_strategy(self.strategy);
+              let evaluation = if self.stepping {
+                step_through(&mut self.editor, &self.engine, &evaluator, &terms, &self.limits)
+                .map_err(|e| e.to_string())?
+              } else {
+                evaluator.evaluate(&terms, &self.limits)
+              };
+              println!("{}", pretty_terms(&self.engine, evaluation.terms.clone()));
+              match evaluator.describe_cycle(&evaluation) {
+                | Some(involved) => println!("({}, through {})", evaluation, involved),
+                | None if evaluation.outcome != Some(Outcome::Normal) =>
+                  println!("({})", evaluation),
+                | None => {},
+              }
+            },
+            | Err(message) => println!("{}", message),
+          }
+        },
+        | Err(ReadlineError::Interrupted) => {},
+        | Err(ReadlineError::Eof) => break,
+        | Err(e) => return Err(e.to_string()),
+      }
+    }
+    if let Some(path) = &self.history {
+      self.editor.save_history(path).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,416 @@
+//! Test files, which pair programs with the normal forms they should rewrite
+//! to, for `mlatu test`.
+//!
+//! A test file (`.mltest`) has one test case per line, written
+//! `name: program => expected`. A line written `name: left == right` is a
+//! property instead: the atoms starting with `$` in it stand for generated
+//! programs, and both sides must rewrite to the same normal form whatever
+//! they are. Blank lines and lines starting with `#` are ignored, and
+//! `include "nat.mlt"` lines name the rule files the cases use, relative to
+//! the test file.
+
+mod diff;
+
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use im::Vector;
+use mlatu_lib::{parse, pretty, Engine, Term};
+use serde_json::json;
+
+use crate::coverage::Coverage;
+use crate::diagnostic::{Diagnostic, Error, Kind};
+use crate::eval::{Evaluator, Limits, Outcome};
+use crate::format::text;
+use crate::property::{self, Config, Generator};
+use crate::{pretty_terms, Library};
+
+/// The extension of test files.
+pub const EXTENSION:&str = "mltest";
+
+/// What a test case checks about its program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Check {
+  /// The program rewrites to this normal form
+  NormalForm(Vector<Term>),
+  /// The program rewrites to the same normal form as this one, for any
+  /// programs the variables in them stand for
+  SameAs(Vector<Term>),
+}
+
+/// A program and what it should rewrite to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+  pub name:String,
+  /// The line of the case in its file, counting from 1
+  pub line:usize,
+  pub input:Vector<Term>,
+  pub check:Check,
+}
+
+/// The test cases in a test file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suite {
+  pub path:PathBuf,
+  /// The rule files the cases use, relative to the current directory
+  pub includes:Vec<PathBuf>,
+  pub cases:Vec<Case>,
+}
+
+/// How the results of running tests are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  /// One line per test, and the details of each failure
+  Text,
+  /// A single JSON object with every result
+  Json,
+}
+
+impl Format {
+  /// Looks up a report format by name.
+  #[must_use]
+  pub fn from_name(name:&str) -> Option<Self> {
+    match name {
+      | "text" => Some(Self::Text),
+      | "json" => Some(Self::Json),
+      | _ => None,
+    }
+  }
+}
+
+/// The result of running one test case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verdict {
+  pub name:String,
+  pub path:PathBuf,
+  pub line:usize,
+  pub passed:bool,
+  /// The pretty-printed terms of the expected normal form
+  pub expected:Vec<String>,
+  /// The pretty-printed terms the program rewrote to, which are only a
+  /// partial result if rewriting did not terminate
+  pub actual:Vec<String>,
+  /// Why rewriting did not reach a normal form, if it did not
+  pub message:Option<String>,
+}
+
+/// Parses one line of a test file into a case, given the byte offset of the
+/// line in the file.
+fn case(engine:&Engine, source:&str, line:&str, offset:usize, number:usize)
+        -> Result<Case, Diagnostic> {
+  let error = |message:&str, bytes:Range<usize>| {
+    Diagnostic::new(Kind::Parse, message).with_span(source,
+                                                    offset + bytes.start..offset + bytes.end)
+  };
+  let start = line.len() - line.trim_start().len();
+  let name_end = line[start..].find(char::is_whitespace).map_or(line.len(), |i| start + i);
+  let name = match line[start..name_end].strip_suffix(':') {
+    | Some(name) if !name.is_empty() => name.to_string(),
+    | _ => return Err(error("expected a test name followed by `:`", start..name_end)),
+  };
+  let arrow = match (line[name_end..].find("=>"), line[name_end..].find("==")) {
+    | (Some(i), Some(j)) => name_end + i.min(j),
+    | (Some(i), None) | (None, Some(i)) => name_end + i,
+    | (None, None) =>
+      return Err(error("expected `=>` or `==` between a program and its result", start..line.len())),
+  };
+  let terms = |bytes:Range<usize>| {
+    parse::terms(engine, &line[bytes.clone()]).map_err(|e| {
+                                                error(&format!("could not parse program: {}", e),
+                                                      bytes)
+                                              })
+  };
+  let input = terms(name_end..arrow)?;
+  let expected = terms(arrow + 2..line.trim_end().len())?;
+  let check = if line[arrow..].starts_with("==") {
+    Check::SameAs(expected)
+  } else {
+    Check::NormalForm(expected)
+  };
+  Ok(Case { name, line:number, input, check })
+}
+
+/// Parses the source text of a test file.
+///
+/// # Errors
+///
+/// Returns `Err` with a diagnostic for every line that is not a test case, a
+/// comment or an include
+pub fn parse_suite(engine:&Engine, path:&Path, source:&str) -> Result<Suite, Error> {
+  let base = path.parent().unwrap_or_else(|| Path::new(""));
+  let mut suite = Suite { path:path.to_path_buf(), includes:Vec::new(), cases:Vec::new() };
+  let mut diagnostics = Vec::new();
+  let mut offset = 0;
+  for (i, line) in source.split_inclusive('\n').enumerate() {
+    let trimmed = line.trim();
+    if !trimmed.is_empty() && !trimmed.starts_with('#') {
+      if let Some(include) = text::include(trimmed.trim_end_matches('.')) {
+        suite.includes.push(base.join(include));
+      } else {
+        match case(engine, source, line, offset, i + 1) {
+          | Ok(case) => suite.cases.push(case),
+          | Err(diagnostic) => diagnostics.push(diagnostic.with_path(path)),
+        }
+      }
+    }
+    offset += line.len();
+  }
+  if diagnostics.is_empty() { Ok(suite) } else { Err(diagnostics.into()) }
+}
+
+/// Reads and parses a test file.
+///
+/// # Errors
+///
+/// Returns `Err` if the file could not be read or parsed
+pub fn read_suite(engine:&Engine, path:&Path) -> Result<Suite, Error> {
+  let source = std::fs::read_to_string(path).map_err(|e| {
+                 Diagnostic::new(Kind::Read, format!("could not read file: {}", e)).with_path(path)
+               })?;
+  parse_suite(engine, path, &source)
+}
+
+fn pretty_each(engine:&Engine, terms:&Vector<Term>) -> Vec<String> {
+  terms.iter().map(|term| pretty::term(engine, term.clone())).collect()
+}
+
+/// Resolves the input of a test case and its expected result, or both sides
+/// of a property.
+fn resolve_case(engine:&Engine, library:&Library, case:&Case)
+                -> Result<(Vector<Term>, Check), String> {
+  let input = library.resolve(engine, &case.input)?;
+  let check = match &case.check {
+    | Check::NormalForm(expected) => Check::NormalForm(library.resolve(engine, expected)?),
+    | Check::SameAs(right) => Check::SameAs(library.resolve(engine, right)?),
+  };
+  Ok((input, check))
+}
+
+/// Runs a test case of a suite with the rules of an evaluator, counting the
+/// rules that fire. Properties are checked as the configuration says.
+pub fn run(engine:&Engine, evaluator:&Evaluator<'_>, suite:&Suite, case:&Case, limits:&Limits,
+           config:&Config, coverage:&mut Coverage)
+           -> Verdict {
+  let library = evaluator.library();
+  let (input, expected) = match resolve_case(engine, library, case) {
+    | Ok((input, Check::NormalForm(expected))) => (input, expected),
+    | Ok((input, Check::SameAs(right))) =>
+      return check_property(engine, evaluator, suite, case, (&input, &right), config, coverage),
+    | Err(message) =>
+      return Verdict { name:case.name.clone(),
+                       path:suite.path.clone(),
+                       line:case.line,
+                       passed:false,
+                       expected:Vec::new(),
+                       actual:Vec::new(),
+                       message:Some(message) },
+  };
+  let evaluation = evaluator.evaluate(&input, limits);
+  coverage.record(library, &evaluation);
+  let finished = evaluation.outcome == Some(Outcome::Normal);
+  let message = if finished {
+    None
+  } else {
+    Some(match evaluator.describe_cycle(&evaluation) {
+      | Some(involved) => format!("{}, through {}", evaluation, involved),
+      | None => evaluation.to_string(),
+    })
+  };
+  Verdict { name:case.name.clone(),
+            path:suite.path.clone(),
+            line:case.line,
+            passed:finished && evaluation.terms == expected,
+            expected:pretty_each(engine, &expected),
+            actual:pretty_each(engine, &evaluation.terms),
+            message }
+}
+
+/// Checks that both sides of a property rewrite to the same normal form for
+/// generated programs, counting the rules that fire, and reporting the normal
+/// forms of the smallest counterexample found.
+fn check_property(engine:&Engine, evaluator:&Evaluator<'_>, suite:&Suite, case:&Case,
+                  (left, right):(&Vector<Term>, &Vector<Term>), config:&Config,
+                  coverage:&mut Coverage)
+                  -> Verdict {
+  let variables = property::variables(engine, &[left, right]);
+  let bind = |inputs:&[Vector<Term>]| {
+    variables.iter().cloned().zip(inputs.iter().cloned()).collect::<Vec<_>>()
+  };
+  let mut generator = Generator::new(engine, &evaluator.library().rules, config);
+  let counterexample = property::check(&mut generator, variables.len(), config.cases, |inputs| {
+    let bindings = bind(inputs);
+    let left = evaluator.evaluate(&property::substitute(engine, left, &bindings), &config.limits);
+    let right = evaluator.evaluate(&property::substitute(engine, right, &bindings), &config.limits);
+    coverage.record(evaluator.library(), &left);
+    coverage.record(evaluator.library(), &right);
+    property::same_result(&left, &right)
+  });
+  let mut verdict = Verdict { name:case.name.clone(),
+                              path:suite.path.clone(),
+                              line:case.line,
+                              passed:counterexample.is_none(),
+                              expected:Vec::new(),
+                              actual:Vec::new(),
+                              message:None };
+  if let Some(inputs) = counterexample {
+    let bindings = bind(&inputs);
+    let left = evaluator.evaluate(&property::substitute(engine, left, &bindings), &config.limits);
+    let right = evaluator.evaluate(&property::substitute(engine, right, &bindings), &config.limits);
+    verdict.actual = pretty_each(engine, &left.terms);
+    verdict.expected = pretty_each(engine, &right.terms);
+    let bound = bindings.iter()
+                        .map(|(variable, terms)| {
+                          format!("{} = {}",
+                                  pretty::term(engine, variable.clone()),
+                                  pretty_terms(engine, terms.clone()))
+                        })
+                        .collect::<Vec<_>>();
+    let mut message = format!("counterexample with seed {}", config.seed);
+    if !bound.is_empty() {
+      message = format!("{}: {}", message, bound.join(", "));
+    }
+    for (side, evaluation) in [("left", &left), ("right", &right)] {
+      if evaluation.outcome != Some(Outcome::Normal) {
+        message = format!("{}; the {} side {}", message, side, evaluation);
+      }
+    }
+    verdict.message = Some(message);
+  }
+  verdict
+}
+
+/// Prints the results of running tests, along with the number of tests that
+/// were not run because of a filter.
+///
+/// # Errors
+///
+/// Returns `Err` if the report could not be written
+pub fn write(verdicts:&[Verdict], filtered:usize, format:Format, out:&mut impl Write)
+             -> io::Result<()> {
+  let passed = verdicts.iter().filter(|verdict| verdict.passed).count();
+  let failed = verdicts.len() - passed;
+  match format {
+    | Format::Text => {
+      for verdict in verdicts {
+        writeln!(out,
+                 "{}:{}: {} ... {}",
+                 verdict.path.display(),
+                 verdict.line,
+                 verdict.name,
+                 if verdict.passed { "ok" } else { "FAILED" })?;
+        if !verdict.passed {
+          writeln!(out, "  expected: {}", verdict.expected.join(" "))?;
+          writeln!(out, "  actual:   {}", verdict.actual.join(" "))?;
+          writeln!(out, "  diff:     {}", diff::words(&verdict.expected, &verdict.actual))?;
+          if let Some(message) = &verdict.message {
+            writeln!(out, "  ({})", message)?;
+          }
+        }
+      }
+      writeln!(out, "{} passed, {} failed, {} filtered out", passed, failed, filtered)
+    },
+    | Format::Json => {
+      let cases = verdicts.iter()
+                          .map(|verdict| {
+                            json!({ "name": verdict.name,
+                                    "path": verdict.path.to_string_lossy(),
+                                    "line": verdict.line,
+                                    "passed": verdict.passed,
+                                    "expected": verdict.expected.join(" "),
+                                    "actual": verdict.actual.join(" "),
+                                    "message": verdict.message })
+                          })
+                          .collect::<Vec<_>>();
+      writeln!(out,
+               "{}",
+               json!({ "passed": passed, "failed": failed, "filtered": filtered, "cases": cases }))
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::{Path, PathBuf};
+
+  use mlatu_lib::{parse, Engine};
+
+  use super::{parse_suite, run, Check};
+  use crate::coverage::{Coverage, Format};
+  use crate::eval::{Evaluator, Limits};
+  use crate::library::tests::parsed;
+  use crate::property::Config;
+
+  #[test]
+  fn parses_cases_properties_and_includes() {
+    let engine = Engine::new();
+    let source = "include \"nat.mlt\".\n# a comment\n\none: a => x\nsame: $x id == $x\n";
+    let suite = parse_suite(&engine, Path::new("dir/t.mltest"), source).expect("the suite parses");
+    assert_eq!(suite.includes, vec![PathBuf::from("dir/nat.mlt")]);
+    let program = |text:&str| parse::terms(&engine, text).expect("the program parses");
+    assert_eq!(suite.cases.len(), 2);
+    assert_eq!((suite.cases[0].name.as_str(), suite.cases[0].line), ("one", 4));
+    assert_eq!(suite.cases[0].check, Check::NormalForm(program("x")));
+    assert_eq!(suite.cases[1].input, program("$x id"));
+    assert_eq!(suite.cases[1].check, Check::SameAs(program("$x")));
+  }
+
+  #[test]
+  fn reports_every_malformed_line() {
+    let engine = Engine::new();
+    let source = "nameless a => b\nok: a => b\nmissing: a b\n";
+    let error =
+      parse_suite(&engine, Path::new("t.mltest"), source).expect_err("the lines are reported");
+    let messages = error.diagnostics.iter().map(|diagnostic| diagnostic.message.as_str());
+    assert_eq!(messages.collect::<Vec<_>>(), vec!["expected a test name followed by `:`",
+                                                  "expected `=>` or `==` between a program and \
+                                                   its result"]);
+  }
+
+  #[test]
+  fn runs_normal_forms_and_properties() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = x. b = y. id = .");
+    let evaluator = Evaluator::new(&engine, &library);
+    let source = "pass: a b => x y\nfail: a => y\nidentity: $x id == $x\nswap: $x $y == $y $x\n";
+    let suite = parse_suite(&engine, Path::new("t.mltest"), source).expect("the suite parses");
+    let config = Config { cases:20, seed:1, ..Config::default() };
+    let verdicts = suite.cases
+                        .iter()
+                        .map(|case| {
+                          run(&engine,
+                              &evaluator,
+                              &suite,
+                              case,
+                              &Limits::default(),
+                              &config,
+                              &mut Coverage::default())
+                        })
+                        .collect::<Vec<_>>();
+    let passed = verdicts.iter().map(|verdict| verdict.passed).collect::<Vec<_>>();
+    assert_eq!(passed, vec![true, false, true, false]);
+    assert_eq!((verdicts[1].expected.clone(), verdicts[1].actual.clone()),
+               (vec!["y".to_string()], vec!["x".to_string()]));
+    let message = verdicts[3].message.as_deref().unwrap_or_default();
+    assert!(message.starts_with("counterexample with seed 1"), "{}", message);
+  }
+
+  #[test]
+  fn counts_the_rules_properties_use() {
+    let engine = Engine::new();
+    let library = parsed(&engine, "a = x. id = .");
+    let evaluator = Evaluator::new(&engine, &library);
+    let source = "identity: $x id == $x\n";
+    let suite = parse_suite(&engine, Path::new("t.mltest"), source).expect("the suite parses");
+    let mut coverage = Coverage::default();
+    coverage.add_library(&engine, &library);
+    let config = Config { cases:5, seed:1, ..Config::default() };
+    let verdict =
+      run(&engine, &evaluator, &suite, &suite.cases[0], &Limits::default(), &config, &mut coverage);
+    assert!(verdict.passed);
+    let mut out = Vec::new();
+    coverage.write(Format::Lcov, &mut out).expect("the report is written");
+    let report = String::from_utf8(out).expect("the report is UTF-8");
+    assert!(!report.contains("FNDA:0,rule 2\n"), "{}", report);
+  }
+}
@@ -0,0 +1,43 @@
+/// A word diff of two sequences of pretty-printed terms, marking the terms
+/// only in `expected` with `[-…-]` and those only in `actual` with `{+…+}`.
+pub fn words(expected:&[String], actual:&[String]) -> String {
+  // The length of the longest common subsequence of each pair of suffixes
+  let mut common = vec![vec![0_usize; actual.len() + 1]; expected.len() + 1];
+  for i in (0..expected.len()).rev() {
+    for j in (0..actual.len()).rev() {
+      common[i][j] = if expected[i] == actual[j] {
+        common[i + 1][j + 1] + 1
+      } else {
+        common[i + 1][j].max(common[i][j + 1])
+      };
+    }
+  }
+  let mut words = Vec::new();
+  let (mut i, mut j) = (0, 0);
+  while i < expected.len() || j < actual.len() {
+    if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+      words.push(expected[i].clone());
+      i += 1;
+      j += 1;
+    } else if j == actual.len() || (i < expected.len() && common[i + 1][j] >= common[i][j + 1]) {
+      words.push(format!("[-{}-]", expected[i]));
+      i += 1;
+    } else {
+      words.push(format!("{{+{}+}}", actual[j]));
+      j += 1;
+    }
+  }
+  words.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::words;
+
+  #[test]
+  fn marks_removed_and_added_words() {
+    let words_of = |text:&str| text.split(' ').map(ToString::to_string).collect::<Vec<_>>();
+    assert_eq!(words(&words_of("a b c"), &words_of("a c d")), "a [-b-] c {+d+}");
+    assert_eq!(words(&words_of("a"), &words_of("a")), "a");
+  }
+}
@@ -0,0 +1,102 @@
+//! Printing the steps of rewriting a program, for `mlatu trace`.
+
+use std::io::{self, Write};
+
+use im::Vector;
+use mlatu_lib::{Engine, Term};
+use serde_json::{json, Value};
+
+use crate::eval::{Evaluation, Evaluator, Fired, Limits, Outcome, Step};
+use crate::pretty_terms;
+
+/// How a trace is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  /// Human-readable text, a few lines per step
+  Text,
+  /// One JSON object per line for each step, and one for the result
+  Json,
+}
+
+impl Format {
+  /// Looks up a trace format by name.
+  #[must_use]
+  pub fn from_name(name:&str) -> Option<Self> {
+    match name {
+      | "text" => Some(Self::Text),
+      | "json" => Some(Self::Json),
+      | _ => None,
+    }
+  }
+}
+
+/// What fired, as JSON.
+pub(crate) fn fired_json(evaluator:&Evaluator<'_>, fired:Fired) -> Value {
+  match fired {
+    | Fired::Rule(index) => match evaluator.library().origin(index) {
+      | Some(origin) => json!({ "rule": { "path": origin.path.to_string_lossy(),
+                                          "index": origin.index,
+                                          "line": origin.lines.as_ref().map(|lines| *lines.start()) } }),
+      | None => json!({ "rule": { "index": index } }),
+    },
+    | Fired::Primitive(primitive) => json!({ "primitive": primitive.symbol() }),
+  }
+}
+
+fn step_json(engine:&Engine, evaluator:&Evaluator<'_>, number:usize, step:Step) -> Value {
+  let mut value = fired_json(evaluator, step.fired);
+  value["step"] = json!(number);
+  value["position"] = json!(step.position);
+  value["before"] = json!(pretty_terms(engine, step.before));
+  value["after"] = json!(pretty_terms(engine, step.after));
+  value
+}
+
+/// Prints every step of rewriting a program within some limits, and then the
+/// normal form or the partial result, returning the finished evaluation.
+///
+/// # Errors
+///
+/// Returns `Err` if the trace could not be written
+pub fn write(engine:&Engine, evaluator:&Evaluator<'_>, terms:&Vector<Term>, limits:&Limits,
+             format:Format, out:&mut impl Write)
+             -> io::Result<Evaluation> {
+  let mut evaluation = evaluator.start(terms);
+  while let Some(step) = evaluator.next_step(&mut evaluation, limits) {
+    let number = evaluation.steps;
+    match format {
+      | Format::Text => {
+        writeln!(out,
+                 "step {}: {} at position {}",
+                 number,
+                 evaluator.describe(step.fired),
+                 step.position)?;
+        writeln!(out, "  before: {}", pretty_terms(engine, step.before))?;
+        writeln!(out, "  after:  {}", pretty_terms(engine, step.after))?;
+      },
+      | Format::Json => writeln!(out, "{}", step_json(engine, evaluator, number, step))?,
+    }
+  }
+  let result = pretty_terms(engine, evaluation.terms.clone());
+  match (format, evaluation.outcome) {
+    | (Format::Text, Some(Outcome::Normal)) =>
+      writeln!(out, "result after {} step(s): {}", evaluation.steps, result)?,
+    | (Format::Text, _) => writeln!(out, "{}: {}", evaluation, result)?,
+    | (Format::Json, outcome) => {
+      let mut value = json!({ "steps": evaluation.steps, "result": result });
+      if outcome != Some(Outcome::Normal) {
+        value["stopped"] = json!(evaluation.to_string());
+      }
+      if let Some(Outcome::Cycle { start, length, }) = outcome {
+        let fired = evaluation.cycle()
+                              .unwrap_or_default()
+                              .iter()
+                              .map(|fired| fired_json(evaluator, *fired))
+                              .collect::<Vec<_>>();
+        value["cycle"] = json!({ "start": start, "length": length, "steps": fired });
+      }
+      writeln!(out, "{}", value)?;
+    },
+  }
+  Ok(evaluation)
+}
@@ -16,6 +16,7 @@ pub enum State {
   AtLeft,
   AtRight,
   Editing(String, Box<Self>),
+  SavingAs(String, Box<Self>),
 }
 
 pub struct View {
@@ -24,13 +25,27 @@ pub struct View {
   sides:Arc<RwLock<Rule>>,
   labels:(String, String),
   default_status:String,
+  /// A label and lines of text shown in the right pane instead of the
+  /// reduction
+  right_pane:Option<(String, Vec<String>)>,
+  /// Kept between reads so that a read can be abandoned without losing
+  /// events
+  events:EventStream,
 }
 
 impl View {
+  const SAVE_AS:&'static str = "Save as: ";
+
   pub fn new(sides:Arc<RwLock<Rule>>, labels:(String, String), default_status:String)
              -> io::Result<Self> {
     let (width, height) = terminal::size()?;
-    Ok(Self { width, height, sides, labels, default_status })
+    Ok(Self { width,
+              height,
+              sides,
+              labels,
+              default_status,
+              right_pane:None,
+              events:EventStream::new() })
   }
 
   fn queue(command:impl Command) -> io::Result<()> { queue!(stdout(), command) }
@@ -40,9 +55,8 @@ impl View {
   pub fn flush() -> io::Result<()> { stdout().flush() }
 
   pub async fn read_key(&mut self) -> io::Result<KeyEvent> {
-    let mut stream = EventStream::new();
     loop {
-      match stream.next().await {
+      match self.events.next().await {
         | Some(Ok(Event::Key(key))) => return Ok(key),
         | Some(Ok(Event::Resize(columns, rows))) => {
           self.width = columns;
@@ -84,6 +98,12 @@ impl View {
 
   async fn make_right_half<F:Fn(&Term) -> String+Copy>(&self, f:F, row:u16, s:&mut String) {
     let width = self.right_half_width(1);
+    if let Some((_, lines)) = &self.right_pane {
+      let line = lines.get(usize::from(row.saturating_sub(2))).map_or("", String::as_str);
+      let line = line.chars().take(width.into()).collect::<String>();
+      s.push_str(&format!("{0: <1$}", line, width.into()));
+      return
+    }
     let guard = self.sides.read().await;
     let term = guard.reduction
                     .get(usize::from(self.height - row).saturating_sub(1))
@@ -117,12 +137,20 @@ impl View {
          + (u16::try_from(s.len()).expect("input field text is greater than 2^16 characters") + 1)
            / 2,
          0),
+      | State::SavingAs(ref s, _) =>
+        (self.width / 2
+         + (u16::try_from(s.len() + Self::SAVE_AS.len()).expect("input field text is greater \
+                                                                 than 2^16 characters")
+            + 1)
+           / 2,
+         0),
     }
   }
 
   fn display_status(&mut self, state:&State) {
     let status = match &state {
       | State::Editing(msg, _) => msg.clone(),
+      | State::SavingAs(path, _) => format!("{}{}", Self::SAVE_AS, path),
       | _ => self.default_status.clone(),
     };
     let width = usize::from(self.width);
@@ -130,7 +158,7 @@ impl View {
     print!("-{0:-^1$}-{2:-^3$}-\r\n",
            self.labels.0,
            self.left_half_width(1).into(),
-           self.labels.1,
+           self.right_pane.as_ref().map_or(&self.labels.1, |(label, _)| label),
            self.right_half_width(1).into());
   }
 
@@ -171,6 +199,21 @@ impl View {
     Self::flush()
   }
 
+  pub fn set_default_status(&mut self, default_status:String) {
+    self.default_status = default_status;
+  }
+
+  pub fn set_right_label(&mut self, label:String) { self.labels.1 = label; }
+
+  /// Shows lines of text with a label in the right pane, or the reduction
+  /// again if `None`.
+  pub fn set_right_pane(&mut self, right_pane:Option<(String, Vec<String>)>) {
+    self.right_pane = right_pane;
+  }
+
+  /// The number of lines the right pane can show.
+  pub const fn pane_height(&self) -> u16 { self.height.saturating_sub(2) }
+
   pub async fn read(&'_ self) -> RwLockReadGuard<'_, Rule> { self.sides.read().await }
 
   pub async fn write(&'_ self) -> RwLockWriteGuard<'_, Rule> { self.sides.write().await }